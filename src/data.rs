@@ -3,7 +3,7 @@
 
 use std::io::BufRead;
 use std::collections::{VecDeque, HashMap};
-use regex::Regex;
+use regex::{Regex, RegexSet};
 use rust_decimal::prelude::*;
 
 use super::{Result, Error};
@@ -12,13 +12,13 @@ type InputType = std::io::BufReader<Box<dyn std::io::Read>>;
 type LinesT = std::io::Lines<InputType>;
 
 // Cat-like access to lines
-struct LineVisitor {
+pub(crate) struct LineVisitor {
     input: VecDeque<String>,
     curr: Option<LinesT>,
 }
 
 impl LineVisitor {
-    fn new(inp: Vec<String>) -> LineVisitor {
+    pub(crate) fn new(inp: Vec<String>) -> LineVisitor {
         if inp.is_empty() {
             // return stdin
             return LineVisitor { input: VecDeque::new(), curr: open_stdio() };
@@ -85,7 +85,7 @@ impl Iterator for LineVisitor {
     }
 }
 
-fn time_from(s: &str, time_select: &Regex) -> Option<Decimal> {
+pub(crate) fn time_from(s: &str, time_select: &Regex) -> Option<Decimal> {
     if let Some(time_match) = time_select.captures(s) {
         if let Some(time) = time_match.name("time").or_else(|| time_match.get(1)) {
             if let Ok(d) = Decimal::from_str_exact(time.as_str()) {
@@ -170,91 +170,460 @@ where
 }
 
 pub fn scoped_time_load(inp: Vec<String>, time_select: &Regex, scoped_in: &Regex, scoped_out: &Regex) -> Vec<Decimal> {
-    scoped_time_parse(LineVisitor::new(inp), time_select, scoped_in, scoped_out)
+    scoped_match_time_parse_keyed(LineVisitor::new(inp), time_select, scoped_in, scoped_out, &ScopeOptions::default())
+        .values()
 }
 
-pub fn scoped_match_time_load(inp: Vec<String>, time_select: &Regex, scoped_in: &Regex, scoped_out: &Regex) -> Result<Vec<Decimal>> {
-    if scoped_in.captures_len() != scoped_out.captures_len() {
-        return Err(Error::ScopedMatchCountError(scoped_in.as_str().into(), scoped_out.as_str().into()));
+/// Values of the regex captures pairing scoped in and out entries
+pub type Key = Vec<String>;
+
+// When the in regex has named captures the key is taken from the captures
+// of those names, ignoring any unnamed groups, so the out regex may
+// capture them in any position.  Otherwise captures pair up by position.
+fn capture_keys(scoped_in: &Regex) -> Option<Vec<String>> {
+    let names: Vec<String> = scoped_in.capture_names()
+        .flatten()
+        .map(String::from)
+        .collect();
+    (!names.is_empty()).then_some(names)
+}
+
+/// Names for each element of a Key matched by regex - its named captures
+/// if it has any, otherwise "key" (or "keyN" for multiple captures)
+pub fn key_names(regex: &Regex) -> Vec<String> {
+    if let Some(names) = capture_keys(regex) {
+        return names;
     }
+    let unnamed_count = regex.captures_len() - 1;
+    regex.capture_names()
+        .skip(1)
+        .enumerate()
+        .map(|(ix, name)| match name {
+            Some(name) => String::from(name),
+            None if unnamed_count == 1 => String::from("key"),
+            None => format!("key{}", ix + 1),
+        })
+        .collect()
+}
 
-    // if not using matched context we can do optimisation by avoiding match handling
-    if scoped_in.captures_len() == 1 {
-        return Ok(scoped_time_load(inp, time_select, scoped_in, scoped_out));
+/// Which open entry an exit closes when several share a key
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Pairing {
+    /// The most recent entry - nested calls and recursion
+    #[default]
+    Lifo,
+    /// The oldest entry - queues, where request N pairs with response N
+    Fifo,
+}
+
+/// How the scoped loaders pair entries with exits
+#[derive(Debug, Default, Clone)]
+pub struct ScopeOptions {
+    pairing: Pairing,
+    timeout: Option<Decimal>,
+    concurrency: bool,
+}
+
+impl ScopeOptions {
+    pub fn set_pairing(&mut self, pairing: Pairing) -> &mut Self {
+        self.pairing = pairing;
+        self
     }
 
-    Ok(scoped_match_time_parse(LineVisitor::new(inp), time_select, scoped_in, scoped_out))
+    /// Drop entries left open for longer than this, so a lost exit can't
+    /// pair with a later entry of the same key
+    pub fn set_timeout(&mut self, timeout: Option<Decimal>) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Record the number of open scopes as the input plays, in
+    /// ScopedData::concurrency
+    pub fn set_concurrency(&mut self, val: bool) -> &mut Self {
+        self.concurrency = val;
+        self
+    }
 }
 
-fn scoped_match_time_parse<I>(inp: I, time_select: &Regex, scoped_in: &Regex, scoped_out: &Regex) -> Vec<Decimal>
+// Open scope entries (line, time) per key, waiting for their exit
+#[derive(Debug, Default)]
+struct Pending {
+    pairing: Pairing,
+    timeout: Option<Decimal>,
+    open: HashMap<Key, VecDeque<(usize, Decimal)>>,
+    len: usize,
+    // Every entry (time, line, key) in the order opened, to find those
+    // timed out without searching every key - only kept with a timeout
+    by_age: VecDeque<(Decimal, usize, Key)>,
+}
+
+impl Pending {
+    fn new(options: &ScopeOptions) -> Self {
+        Pending { pairing: options.pairing, timeout: options.timeout, ..Default::default() }
+    }
+
+    fn push(&mut self, key: Key, line: usize, time: Decimal) {
+        if self.timeout.is_some() {
+            self.by_age.push_back((time, line, key.clone()));
+        }
+        self.open.entry(key)
+            .or_default()
+            .push_back((line, time));
+        self.len += 1;
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn take(&mut self, key: &Key) -> Option<(usize, Decimal)> {
+        let entries = self.open.get_mut(key)?;
+        let taken = match self.pairing {
+            Pairing::Lifo => entries.pop_back(),
+            Pairing::Fifo => entries.pop_front(),
+        };
+        if entries.is_empty() {
+            self.open.remove(key);
+        }
+        if taken.is_some() {
+            self.len -= 1;
+        }
+        taken
+    }
+
+    // Remove the entries open for longer than the timeout at time now
+    fn expire(&mut self, now: Decimal) -> Vec<ScopeEvent> {
+        let mut expired = vec![];
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return expired,
+        };
+        while self.by_age.front().map_or(false, |(time, _, _)| now - *time > timeout) {
+            let (_, line, key) = self.by_age.pop_front().expect("front checked");
+            // Already gone if its exit was seen
+            if let Some(entries) = self.open.get_mut(&key) {
+                if let Some(ix) = entries.iter().position(|(l, _)| *l == line) {
+                    entries.remove(ix);
+                    self.len -= 1;
+                    if entries.is_empty() {
+                        self.open.remove(&key);
+                    }
+                    expired.push(ScopeEvent { line, key });
+                }
+            }
+        }
+        expired
+    }
+}
+
+/// A scope entry or exit that could not be paired
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeEvent {
+    /// Line number, counting from 1 across all of the input
+    pub line: usize,
+    pub key: Key,
+}
+
+/// Scope entries and exits left unpaired by the scoped loaders
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ScopeDiagnostics {
+    /// Exits seen with no open entry for their key
+    pub unmatched_exits: Vec<ScopeEvent>,
+    /// Entries still open at the end of the input
+    pub open_at_eof: Vec<ScopeEvent>,
+    /// Entries never exited, found open when an enclosing scope exited
+    pub abandoned: Vec<ScopeEvent>,
+    /// Entries dropped when open for longer than the scope timeout
+    pub timed_out: Vec<ScopeEvent>,
+}
+
+impl ScopeDiagnostics {
+    // Events listed per category, the rest are just counted
+    const REPORT_LIMIT: usize = 10;
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of entries and exits that could not be paired
+    pub fn len(&self) -> usize {
+        self.unmatched_exits.len() + self.open_at_eof.len() + self.abandoned.len() + self.timed_out.len()
+    }
+
+    fn add_open(&mut self, pending: Pending) {
+        self.open_at_eof.extend(pending.open.into_iter()
+                                .flat_map(|(key, entries)| entries.into_iter()
+                                          .map(move |(line, _)| ScopeEvent { line, key: key.clone() })));
+        self.open_at_eof.sort_by_key(|e| e.line);
+    }
+
+    fn write_events(w: &mut dyn std::fmt::Write, what: &str, events: &[ScopeEvent], key_names: &[String]) -> Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        writeln!(w, "{} {}", events.len(), what)?;
+        for e in events.iter().take(Self::REPORT_LIMIT) {
+            write!(w, "  line {}", e.line)?;
+            for (name, value) in key_names.iter().zip(&e.key) {
+                write!(w, " {}={}", name, value)?;
+            }
+            writeln!(w)?;
+        }
+        if events.len() > Self::REPORT_LIMIT {
+            writeln!(w, "  ... and {} more", events.len() - Self::REPORT_LIMIT)?;
+        }
+        Ok(())
+    }
+
+    /// Human readable summary, naming the key values with key_names
+    pub fn write_report(&self, w: &mut dyn std::fmt::Write, key_names: &[String]) -> Result<()> {
+        Self::write_events(w, "unmatched scope exit(s)", &self.unmatched_exits, key_names)?;
+        Self::write_events(w, "scope(s) abandoned by an enclosing exit", &self.abandoned, key_names)?;
+        Self::write_events(w, "scope(s) timed out", &self.timed_out, key_names)?;
+        Self::write_events(w, "scope(s) still open at end of input", &self.open_at_eof, key_names)
+    }
+}
+
+/// Scopes paired by the scoped loaders
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ScopedData {
+    /// Key and duration of each paired scope, in the order they closed
+    pub durations: Vec<(Key, Decimal)>,
+    /// Number of scopes open after each change, with the time of the
+    /// change - only recorded if ScopeOptions::set_concurrency() is set
+    pub concurrency: Vec<(Decimal, usize)>,
+    pub diagnostics: ScopeDiagnostics,
+}
+
+impl ScopedData {
+    /// Durations without their keys
+    pub fn values(&self) -> Vec<Decimal> {
+        self.durations.iter().map(|(_, duration)| *duration).collect()
+    }
+
+    fn record_concurrency(&mut self, now: Decimal, open: usize) {
+        if self.concurrency.last().map(|(_, last)| *last) != Some(open) {
+            self.concurrency.push((now, open));
+        }
+    }
+
+    // End the timeline at the last line, so the time since the last change
+    // counts too
+    fn end_concurrency(&mut self, end: Decimal) {
+        if let Some(&(time, open)) = self.concurrency.last() {
+            if end > time {
+                self.concurrency.push((end, open));
+            }
+        }
+    }
+}
+
+pub fn scoped_match_time_load(inp: Vec<String>, time_select: &Regex, scoped_in: &Regex, scoped_out: &Regex) -> Result<Vec<Decimal>> {
+    Ok(scoped_match_time_load_keyed(inp, time_select, scoped_in, scoped_out, &ScopeOptions::default())?
+       .values())
+}
+
+/// As scoped_match_time_load() but keeping the Key of each duration, and
+/// reporting the entries and exits that could not be paired
+pub fn scoped_match_time_load_keyed(inp: Vec<String>, time_select: &Regex, scoped_in: &Regex, scoped_out: &Regex, options: &ScopeOptions) -> Result<ScopedData> {
+    check_scope_keys(scoped_in, scoped_out)?;
+    Ok(scoped_match_time_parse_keyed(LineVisitor::new(inp), time_select, scoped_in, scoped_out, options))
+}
+
+/// A named pair of scope in and out regexes
+#[derive(Debug, Clone)]
+pub struct ScopePair {
+    pub name: String,
+    pub scoped_in: Regex,
+    pub scoped_out: Regex,
+}
+
+/// Parse scope pairs, one per line as tab separated name, in regex and
+/// out regex.  The out regex may be left off to toggle on the in regex,
+/// as for --scope-match.  Blank lines and lines starting '#' are skipped.
+pub fn parse_scopes(s: &str) -> Result<Vec<ScopePair>> {
+    let mut pairs = vec![];
+    for (line, text) in (1..).zip(s.lines()) {
+        if text.trim().is_empty() || text.trim_start().starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = text.split('\t').collect();
+        let (name, scoped_in, scoped_out) = match fields[..] {
+            [name, scoped_in] => (name, scoped_in, scoped_in),
+            [name, scoped_in, scoped_out] => (name, scoped_in, scoped_out),
+            _ => return Err(Error::ScopesFileError(line, String::from("expected name, in regex and optional out regex separated by tabs"))),
+        };
+        let regex = |s: &str| Regex::new(s).map_err(|e| Error::ScopesFileError(line, e.to_string()));
+        let (scoped_in, scoped_out) = (regex(scoped_in)?, regex(scoped_out)?);
+        check_scope_keys(&scoped_in, &scoped_out)?;
+        let name = name.trim();
+        if pairs.iter().any(|pair: &ScopePair| pair.name == name) {
+            return Err(Error::ScopesFileError(line, format!("duplicate scope name {}", name)));
+        }
+        pairs.push(ScopePair { name: String::from(name), scoped_in, scoped_out });
+    }
+    Ok(pairs)
+}
+
+/// As scoped_match_time_load_keyed() for several scopes in one pass of
+/// the input, returning the data of each in the order given
+pub fn scopes_load(inp: Vec<String>, time_select: &Regex, scopes: &[ScopePair], options: &ScopeOptions) -> Result<Vec<ScopedData>> {
+    scopes_parse(LineVisitor::new(inp), time_select, scopes, options)
+}
+
+fn scopes_parse<I>(inp: I, time_select: &Regex, scopes: &[ScopePair], options: &ScopeOptions) -> Result<Vec<ScopedData>>
 where
     I: Iterator<Item = String>
 {
-    type Key = Vec<String>;
-    fn match_to_key(regex: &Regex, line: &str) -> Option<Key> {
-        regex.captures(line)
-            .map(|the_match| the_match
-                 .iter()
-                 .skip(1)
-                 .map(|y| y.unwrap().as_str().into())
-                 .collect())
+    // Pattern 2n is scope n's in regex, 2n + 1 its out regex
+    let set = RegexSet::new(scopes.iter()
+                            .flat_map(|scope| [scope.scoped_in.as_str(), scope.scoped_out.as_str()]))?;
+    let mut matchers: Vec<ScopeMatcher> = scopes.iter()
+        .map(|scope| ScopeMatcher::new(&scope.scoped_in, &scope.scoped_out, options))
+        .collect();
+
+    let mut last = None;
+    for (line, x) in (1..).zip(inp) {
+        // cheaply skip the lines that no scope is interested in - unless
+        // open entries may time out on them
+        let matched = set.matches(&x);
+        if !matched.matched_any() && options.timeout.is_none() {
+            continue;
+        }
+        if let Some(now) = time_from(x.as_str(), time_select) {
+            // every scope's entries expire on every line, as for a pass
+            // of each scope alone
+            if options.timeout.is_some() {
+                matchers.iter_mut().for_each(|matcher| matcher.expire(now));
+            }
+            let mut scope_ixs: Vec<usize> = matched.iter().map(|ix| ix / 2).collect();
+            scope_ixs.dedup();
+            for ix in scope_ixs {
+                matchers[ix].step(line, &x, now);
+            }
+            last = Some(now);
+        }
     }
+    Ok(matchers.into_iter().map(|matcher| matcher.finish(last)).collect())
+}
 
-    let mut v: Vec<Decimal> = vec![];
-    let mut prev: HashMap<Key, Vec<Decimal>> = HashMap::new();
+// The same regex marks both ends of a scope - each match toggles the scope
+// for its key, the first opening it and the next closing it
+fn is_symmetric(scoped_in: &Regex, scoped_out: &Regex) -> bool {
+    std::ptr::eq(scoped_in, scoped_out) || (scoped_in.as_str() == scoped_out.as_str())
+}
 
-    let symmetric: bool = std::ptr::eq(scoped_in, scoped_out) || (scoped_in.as_str() == scoped_out.as_str());
+fn check_scope_keys(scoped_in: &Regex, scoped_out: &Regex) -> Result<()> {
+    let matching = match capture_keys(scoped_in) {
+        Some(names) => names.iter()
+            .all(|name| scoped_out.capture_names().any(|out| out == Some(name.as_str()))),
+        None => scoped_in.captures_len() == scoped_out.captures_len(),
+    };
+    if !matching {
+        return Err(Error::ScopedMatchCountError(scoped_in.as_str().into(), scoped_out.as_str().into()));
+    }
+    Ok(())
+}
 
-    for x in inp {
-        if let Some(now) = time_from(x.as_str(), time_select) {
-            if let Some(match_key) = match_to_key(scoped_in, &x) {
-                prev.entry(match_key)
-                    .or_default()
-                    .push(now);
-
-                if symmetric {
-                    // Don't look for end match?
-                    // maybe always continue?
-                    continue;
+// captures that took no part in the match, eg in an optional group, key as
+// the empty string
+fn match_to_key(regex: &Regex, names: Option<&[String]>, line: &str) -> Option<Key> {
+    // if not using matched context we can do optimisation by avoiding match handling
+    if regex.captures_len() == 1 {
+        return regex.is_match(line).then(Vec::new);
+    }
+    regex.captures(line)
+        .map(|the_match| match names {
+            Some(names) => names.iter()
+                .map(|name| the_match.name(name).map_or("", |y| y.as_str()).into())
+                .collect(),
+            None => the_match.iter()
+                .skip(1)
+                .map(|y| y.map_or("", |y| y.as_str()).into())
+                .collect(),
+        })
+}
+
+// Pairs the entries and exits of one scope, a line at a time
+struct ScopeMatcher<'a> {
+    scoped_in: &'a Regex,
+    scoped_out: &'a Regex,
+    symmetric: bool,
+    names: Option<Vec<String>>,
+    concurrency: bool,
+    prev: Pending,
+    data: ScopedData,
+}
+
+impl<'a> ScopeMatcher<'a> {
+    fn new(scoped_in: &'a Regex, scoped_out: &'a Regex, options: &ScopeOptions) -> Self {
+        ScopeMatcher {
+            scoped_in,
+            scoped_out,
+            symmetric: is_symmetric(scoped_in, scoped_out),
+            names: capture_keys(scoped_in),
+            concurrency: options.concurrency,
+            prev: Pending::new(options),
+            data: ScopedData::default(),
+        }
+    }
+
+    fn expire(&mut self, now: Decimal) {
+        self.data.diagnostics.timed_out.extend(self.prev.expire(now));
+    }
+
+    fn step(&mut self, line: usize, x: &str, now: Decimal) {
+        self.expire(now);
+        if self.symmetric {
+            if let Some(match_key) = match_to_key(self.scoped_in, self.names.as_deref(), x) {
+                match self.prev.take(&match_key) {
+                    Some((_, then)) => self.data.durations.push((match_key, now - then)),
+                    None => self.prev.push(match_key, line, now),
                 }
             }
+        } else {
+            if let Some(match_key) = match_to_key(self.scoped_in, self.names.as_deref(), x) {
+                self.prev.push(match_key, line, now);
+            }
 
-            if let Some(match_key) = match_to_key(scoped_out, &x) {
-                if let Some(then) = prev.get_mut(&match_key).and_then(Vec::<_>::pop) {
-                    v.push(now - then);
+            if let Some(match_key) = match_to_key(self.scoped_out, self.names.as_deref(), x) {
+                if let Some((_, then)) = self.prev.take(&match_key) {
+                    self.data.durations.push((match_key, now - then));
                 } else {
-                    println!(" not matched {:?}", match_key);
+                    self.data.diagnostics.unmatched_exits.push(ScopeEvent { line, key: match_key });
                 }
             }
         }
+        if self.concurrency {
+            self.data.record_concurrency(now, self.prev.len());
+        }
+    }
+
+    // last is the time of the final line seen
+    fn finish(mut self, last: Option<Decimal>) -> ScopedData {
+        if let Some(now) = last {
+            self.data.end_concurrency(now);
+        }
+        self.data.diagnostics.add_open(self.prev);
+        self.data
     }
-    v
 }
 
-fn scoped_time_parse<I>(inp: I, time_select: &Regex, scoped_in: &Regex, scoped_out: &Regex) -> Vec<Decimal>
+fn scoped_match_time_parse_keyed<I>(inp: I, time_select: &Regex, scoped_in: &Regex, scoped_out: &Regex, options: &ScopeOptions) -> ScopedData
 where
     I: Iterator<Item = String>
 {
-    let mut v: Vec<Decimal> = vec![];
-    let mut prev: Vec<Decimal> = vec![];
-    for x in inp {
-        let time = time_from(x.as_str(), time_select);
-        if let Some(now) = time {
-            if scoped_in.is_match(&x) {
-                prev.push(now);
-            } else if scoped_out.is_match(&x) {
-                if let Some(then) = prev.pop() {
-                    v.push(now - then);
-                } else {
-                    println!(" not matched");
-                }
-            }
+    let mut matcher = ScopeMatcher::new(scoped_in, scoped_out, options);
+    let mut last = None;
+    for (line, x) in (1..).zip(inp) {
+        if let Some(now) = time_from(x.as_str(), time_select) {
+            matcher.step(line, &x, now);
+            last = Some(now);
         }
     }
-    v
+    matcher.finish(last)
 }
 
-
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
@@ -277,13 +646,21 @@ mod tests {
     }
 
     fn r(s: &str) -> Regex {
-        Regex::new(s).expect(format!("regex failed to compile '{}'", s).as_str())
+        Regex::new(s).unwrap_or_else(|_| panic!("regex failed to compile '{}'", s))
     }
 
     fn ro(s: &str) -> Option<Regex> {
         Some(r(s))
     }
 
+    fn scoped_match_time_parse<I>(inp: I, time_select: &Regex, scoped_in: &Regex, scoped_out: &Regex) -> Vec<Decimal>
+    where
+        I: Iterator<Item = String>
+    {
+        scoped_match_time_parse_keyed(inp, time_select, scoped_in, scoped_out, &ScopeOptions::default())
+            .values()
+    }
+
     // use BufReader::lines() like the main application does
     // (resolves windows issues)
     fn to_lines(s: &str) -> std::vec::IntoIter<String> {
@@ -309,6 +686,10 @@ mod tests {
         v.iter().map(|x| Decimal::from_str_exact(x).unwrap()).collect()
     }
 
+    fn values(data: &[(Key, Decimal)]) -> Vec<Decimal> {
+        data.iter().map(|(_, v)| *v).collect()
+    }
+
     #[test]
     fn test_diff_parse() {
         let d = include_str!("../tests/example.txt");
@@ -367,12 +748,12 @@ mod tests {
     #[test]
     fn test_scoped_load_simple_in_out() {
         let d = include_str!("../tests/example_scoped.txt");
-        let data = scoped_time_parse(to_lines(d), &default_time(),
-                                     &r(r"->reset"), &r(r"<-reset"));
+        let data = scoped_match_time_parse_keyed(to_lines(d), &default_time(),
+                                     &r(r"->reset"), &r(r"<-reset"), &ScopeOptions::default()).values();
         assert_eq!(data, dec_v(vec![ "900.1583", "203.8183",]));
 
-        let data = scoped_time_parse(to_lines(d), &default_time(),
-                                     &r(r"->recurse"), &r(r"<-recurse"));
+        let data = scoped_match_time_parse_keyed(to_lines(d), &default_time(),
+                                     &r(r"->recurse"), &r(r"<-recurse"), &ScopeOptions::default()).values();
         assert_eq!(data, dec_v(vec![ "60.2755", "3288.0172", "5699.9640", "1.0000",]));
     }
 
@@ -394,6 +775,214 @@ mod tests {
         assert_eq!(data, dec_v(vec![ "900.1583", "203.8183",  "60.2755", "3288.0172", "5699.9640", "1.0000"]));
     }
 
+    #[test]
+    fn test_scoped_pairing() {
+        let d = include_str!("../tests/example_scoped.txt");
+        let mut fifo = ScopeOptions::default();
+        fifo.set_pairing(Pairing::Fifo);
+
+        let ScopedData { durations: data, diagnostics, .. } = scoped_match_time_parse_keyed(to_lines(d), &default_time(),
+                                                    &r(r"->recurse"), &r(r"<-recurse"), &fifo);
+        assert_eq!(values(&data), dec_v(vec![ "2328.2464", "3288.0172", "3431.9931", "1.0000",]));
+        assert!(diagnostics.is_empty());
+
+        let ScopedData { durations: data, diagnostics, .. } = scoped_match_time_parse_keyed(to_lines(d), &default_time(),
+                                                                &r(r"->(reset|recurse)"), &r(r"<-(reset|recurse)"), &fifo);
+        let durations = values(&data);
+        assert_eq!(durations, dec_v(vec![ "900.1583", "1799.9357", "2328.2464", "3288.0172", "3431.9931", "1.0000"]));
+        // fifo leaves the latest reset open
+        assert_eq!(diagnostics.open_at_eof, vec![ScopeEvent { line: 11, key: vec![String::from("reset")] }]);
+
+        // lifo, the default, closes the most recent entry
+        let ScopedData { durations: data, diagnostics, .. } = scoped_match_time_parse_keyed(to_lines(d), &default_time(),
+                                                    &r(r"->reset"), &r(r"<-reset"), &ScopeOptions::default());
+        assert_eq!(values(&data), dec_v(vec![ "900.1583", "203.8183",]));
+        assert_eq!(diagnostics.open_at_eof, vec![ScopeEvent { line: 7, key: vec![] }]);
+    }
+
+    #[test]
+    fn test_scoped_timeout() {
+        let d = include_str!("../tests/example_scoped.txt");
+        let mut options = ScopeOptions::default();
+        options.set_timeout(Some(Decimal::new(3000, 0)));
+
+        let ScopedData { durations: data, diagnostics, .. } = scoped_match_time_parse_keyed(to_lines(d), &default_time(),
+                                                    &r(r"->recurse"), &r(r"<-recurse"), &options);
+        assert_eq!(values(&data), dec_v(vec![ "60.2755", "1.0000",]));
+        let lines = |events: &[ScopeEvent]| events.iter().map(|e| e.line).collect::<Vec<_>>();
+        assert_eq!(lines(&diagnostics.timed_out), vec![14, 15]);
+        assert_eq!(lines(&diagnostics.unmatched_exits), vec![20, 23]);
+
+        // a lost exit no longer pairs with the key's next entry
+        let d = "1.0 open 3\n1.5 open 4\n2.0 close 4\n9.0 open 3\n9.5 close 3\n";
+        let (in_, out) = (r(r"open (\d)"), r(r"close (\d)"));
+        let mut fifo = ScopeOptions::default();
+        fifo.set_pairing(Pairing::Fifo);
+        let ScopedData { durations: data, .. } = scoped_match_time_parse_keyed(to_lines(d), &default_time(), &in_, &out, &fifo);
+        assert_eq!(values(&data), dec_v(vec!["0.5", "8.5"]));
+
+        fifo.set_timeout(Some(Decimal::new(5, 0)));
+        let ScopedData { durations: data, diagnostics, .. } = scoped_match_time_parse_keyed(to_lines(d), &default_time(), &in_, &out, &fifo);
+        assert_eq!(values(&data), dec_v(vec!["0.5", "0.5"]));
+        assert_eq!(diagnostics.timed_out, vec![ScopeEvent { line: 1, key: vec![String::from("3")] }]);
+        assert!(diagnostics.open_at_eof.is_empty());
+
+        // entries stale by the last line time out rather than staying open,
+        // even on lines no scope matches
+        let d = "1.0 open 3\n2.0 open 4\n7.0 other\n";
+        let ScopedData { diagnostics, .. } = scoped_match_time_parse_keyed(to_lines(d), &default_time(), &in_, &out, &fifo);
+        assert_eq!(lines(&diagnostics.timed_out), vec![1]);
+        assert_eq!(lines(&diagnostics.open_at_eof), vec![2]);
+        let fd = ScopePair { name: String::from("fd"), scoped_in: in_, scoped_out: out };
+        let data = scopes_parse(to_lines(d), &default_time(), &[fd.clone()], &fifo).unwrap();
+        assert_eq!(lines(&data[0].diagnostics.timed_out), vec![1]);
+        assert_eq!(lines(&data[0].diagnostics.open_at_eof), vec![2]);
+
+        // including lines only another scope matches
+        let other = ScopePair { name: String::from("other"), scoped_in: r("other"), scoped_out: r("other") };
+        let data = scopes_parse(to_lines(d), &default_time(), &[fd, other], &fifo).unwrap();
+        assert_eq!(lines(&data[0].diagnostics.timed_out), vec![1]);
+        assert_eq!(lines(&data[0].diagnostics.open_at_eof), vec![2]);
+    }
+
+    #[test]
+    fn test_scoped_concurrency() {
+        let d = "1.0 ->a\n2.0 ->a\n3.0 <-a\n4.0 other\n5.0 <-a\n6.0 <-a\n";
+        let mut options = ScopeOptions::default();
+        options.set_concurrency(true);
+        let data = scoped_match_time_parse_keyed(to_lines(d), &default_time(), &r(r"->"), &r(r"<-"), &options);
+        let times = dec_v(vec!["1.0", "2.0", "3.0", "5.0", "6.0"]);
+        assert_eq!(data.concurrency, times.into_iter().zip([1, 2, 1, 0, 0]).collect::<Vec<_>>());
+
+        // the timeline runs to the last line, with scopes still open
+        let d = "1.0 ->a\n2.0 ->a\n3.0 <-a\n4.0 other\n";
+        let data = scoped_match_time_parse_keyed(to_lines(d), &default_time(), &r(r"->"), &r(r"<-"), &options);
+        let times = dec_v(vec!["1.0", "2.0", "3.0", "4.0"]);
+        assert_eq!(data.concurrency, times.into_iter().zip([1, 2, 1, 1]).collect::<Vec<_>>());
+
+        // only recorded on request
+        let data = scoped_match_time_parse_keyed(to_lines(d), &default_time(), &r(r"->"), &r(r"<-"), &ScopeOptions::default());
+        assert!(data.concurrency.is_empty());
+    }
+
+    #[test]
+    fn test_scopes() {
+        let scopes = parse_scopes("# name\tin\tout\n\nreset\t->reset\t<-reset\nkeyed\t->(reset|recurse)\t<-(reset|recurse)\ndelay\treset delay\n").unwrap();
+        let names: Vec<&str> = scopes.iter().map(|scope| scope.name.as_str()).collect();
+        assert_eq!(names, vec!["reset", "keyed", "delay"]);
+
+        let d = include_str!("../tests/example_scoped.txt");
+        // the same as a pass for each
+        for options in [ScopeOptions::default(), ScopeOptions::default().set_timeout(Some(Decimal::new(1000, 0))).clone()] {
+            let data = scopes_parse(to_lines(d), &default_time(), &scopes, &options).unwrap();
+            assert_eq!(data.len(), 3);
+            for (scope, data) in scopes.iter().zip(&data) {
+                assert_eq!(data, &scoped_match_time_parse_keyed(to_lines(d), &default_time(), &scope.scoped_in,
+                                                                 &scope.scoped_out, &options));
+            }
+        }
+        let data = scopes_parse(to_lines(d), &default_time(), &scopes, &ScopeOptions::default()).unwrap();
+        assert_eq!(data[2].values(), dec_v(vec!["2340.0893"]));
+
+        assert!(matches!(parse_scopes("name only\n"), Err(Error::ScopesFileError(1, _))));
+        assert!(matches!(parse_scopes("\nbad\t(\n"), Err(Error::ScopesFileError(2, _))));
+        assert!(matches!(parse_scopes("bad\t(a)\tb\n"), Err(Error::ScopedMatchCountError(_, _))));
+        assert!(matches!(parse_scopes("a\tx\ny\tz\na\tz\n"), Err(Error::ScopesFileError(3, _))));
+    }
+
+    #[test]
+    fn test_scoped_toggle() {
+        let d = include_str!("../tests/example_scoped.txt");
+        let toggle = r(r"reset delay");
+        let ScopedData { durations: data, diagnostics, .. } = scoped_match_time_parse_keyed(to_lines(d), &default_time(),
+                                                    &toggle, &toggle, &ScopeOptions::default());
+        assert_eq!(values(&data), dec_v(vec![ "2340.0893"]));
+        assert!(diagnostics.is_empty());
+
+        let toggle = r(r"(?:->|<-)(waitReset|other)");
+        let ScopedData { durations: data, diagnostics, .. } = scoped_match_time_parse_keyed(to_lines(d), &default_time(),
+                                                                &toggle, &toggle, &ScopeOptions::default());
+        let durations = values(&data);
+        assert_eq!(durations, dec_v(vec![ "281.9118", "408.2374", "2520.3173", "1013.8559", "545.7759"]));
+        assert!(diagnostics.is_empty());
+
+        // an odd number of matches leaves the last open
+        let toggle = r(r"->(reset)");
+        let ScopedData { durations: data, diagnostics, .. } = scoped_match_time_parse_keyed(to_lines(d), &default_time(),
+                                                                &toggle, &toggle, &ScopeOptions::default());
+        assert_eq!(data, vec![(vec![String::from("reset")], dec_v(vec!["1895.9806"])[0])]);
+        assert_eq!(diagnostics.open_at_eof, vec![ScopeEvent { line: 11, key: vec![String::from("reset")] }]);
+    }
+
+    #[test]
+    fn test_scoped_match_keyed() {
+        let d = include_str!("../tests/example_scoped.txt");
+        let ScopedData { durations: data, diagnostics, .. } = scoped_match_time_parse_keyed(to_lines(d), &default_time(),
+                                                                &r(r"->(reset|recurse)"), &r(r"<-(reset|recurse)"),
+                                                                &ScopeOptions::default());
+        let keys: Vec<&str> = data.iter().map(|(k, _)| k[0].as_str()).collect();
+        assert_eq!(keys, vec!["reset", "reset", "recurse", "recurse", "recurse", "recurse"]);
+        assert_eq!(diagnostics, ScopeDiagnostics {
+            open_at_eof: vec![ScopeEvent { line: 7, key: vec![String::from("reset")] }],
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_scoped_named_keys() {
+        let d = "1.0 req id=7 op=get\n1.5 req id=8 op=put\n2.0 resp op=put status=ok id=8\n3.5 resp op=get status=err id=7\n4.0 req id=9\n4.25 resp status=ok id=9\n";
+        let scoped_in = r(r"req id=(?<id>\d+)(?: op=(?<op>\w+))?");
+        let scoped_out = r(r"resp (?:op=(?<op>\w+) )?status=(\w+) id=(?<id>\d+)");
+        assert!(check_scope_keys(&scoped_in, &scoped_out).is_ok());
+        let ScopedData { durations: data, diagnostics, .. } = scoped_match_time_parse_keyed(
+            to_lines(d), &default_time(), &scoped_in, &scoped_out, &ScopeOptions::default());
+        let keys: Vec<Vec<&str>> = data.iter().map(|(k, _)| k.iter().map(String::as_str).collect()).collect();
+        assert_eq!(keys, vec![vec!["8", "put"], vec!["7", "get"], vec!["9", ""]]);
+        assert_eq!(values(&data), dec_v(vec!["0.5", "2.5", "0.25"]));
+        assert!(diagnostics.is_empty());
+        assert_eq!(key_names(&scoped_in), vec!["id", "op"]);
+
+        // every in name must be captured by the out regex
+        assert!(matches!(check_scope_keys(&scoped_in, &r(r"resp (\w+)")), Err(Error::ScopedMatchCountError(_, _))));
+        // unnamed groups are left out of the key
+        let scoped_in = r(r"(req|call) id=(?<id>\d+)");
+        assert!(check_scope_keys(&scoped_in, &scoped_out).is_ok());
+        assert!(matches!(check_scope_keys(&scoped_in, &r(r"resp (\w+) (\d+)")), Err(Error::ScopedMatchCountError(_, _))));
+        let ScopedData { durations: data, .. } = scoped_match_time_parse_keyed(
+            to_lines(d), &default_time(), &scoped_in, &scoped_out, &ScopeOptions::default());
+        let keys: Vec<&str> = data.iter().map(|(k, _)| k[0].as_str()).collect();
+        assert_eq!(keys, vec!["8", "7", "9"]);
+    }
+
+    #[test]
+    fn test_scope_diagnostics() {
+        let d = "1.0 ->a\n2.0 <-a\n3.0 <-a\n4.0 ->a\n5.0 ->b\n";
+        let ScopedData { durations: data, diagnostics, .. } = scoped_match_time_parse_keyed(to_lines(d), &default_time(), &r(r"->"), &r(r"<-"), &ScopeOptions::default());
+        assert_eq!(values(&data), dec_v(vec!["1"]));
+        assert_eq!(diagnostics.unmatched_exits, vec![ScopeEvent { line: 3, key: vec![] }]);
+        assert_eq!(diagnostics.open_at_eof.iter().map(|e| e.line).collect::<Vec<_>>(), vec![4, 5]);
+
+        let ScopedData { diagnostics, .. } = scoped_match_time_parse_keyed(to_lines(d), &default_time(), &r(r"->(\w)"), &r(r"<-(\w)"),
+                                                             &ScopeOptions::default());
+        let mut s = String::new();
+        diagnostics.write_report(&mut s, &[String::from("op")]).unwrap();
+        assert_eq!(s, "1 unmatched scope exit(s)
+  line 3 op=a
+2 scope(s) still open at end of input
+  line 4 op=a
+  line 5 op=b
+");
+        assert!(ScopeDiagnostics::default().is_empty());
+    }
+
+    #[test]
+    fn test_key_names() {
+        assert_eq!(key_names(&r(r"->reset")), Vec::<String>::new());
+        assert_eq!(key_names(&r(r"->(reset|recurse)")), vec!["key"]);
+        assert_eq!(key_names(&r(r"(\d+) (\w+)")), vec!["key1", "key2"]);
+        assert_eq!(key_names(&r(r"(?<fd>\d+) (\w+)")), vec!["fd"]);
+    }
+
     #[test]
     fn test_scoped_match_strace() {
         let d = include_str!("../tests/strace.txt");
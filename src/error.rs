@@ -10,6 +10,11 @@ pub enum Error {
     FormatError(std::fmt::Error),
     ParseIntError(std::num::ParseIntError),
     ScopedMatchCountError(String, String),
+    UnsupportedOutput(String),
+    InvalidLabelName(String),
+    UnmatchedScopes(usize),
+    ScopesFileError(usize, String),
+    RegexError(regex::Error),
 }
 
 impl Error {
@@ -35,6 +40,16 @@ impl std::fmt::Display for Error {
                 write!(f, "Format error {}", e),
             Error::ScopedMatchCountError(i, o) =>
                 write!(f, "Scoped regexes don't have matching captures '{}' '{}'", i, o),
+            Error::UnsupportedOutput(o) =>
+                write!(f, "Output {} is not supported for this data", o),
+            Error::InvalidLabelName(l) =>
+                write!(f, "Invalid OpenMetrics label name {}", l),
+            Error::UnmatchedScopes(n) =>
+                write!(f, "{} scope entries or exits could not be paired", n),
+            Error::ScopesFileError(line, e) =>
+                write!(f, "Scopes file line {}: {}", line, e),
+            Error::RegexError(e) =>
+                write!(f, "Regex error {}", e),
         }
     }
 }
@@ -43,7 +58,9 @@ impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match *self {
             Error::NoData | Error::DataTagsTooLongToFitTerminal(_) |
-            Error::ScopedMatchCountError(_, _) => None,
+            Error::ScopedMatchCountError(_, _) | Error::UnsupportedOutput(_) | Error::InvalidLabelName(_) |
+            Error::UnmatchedScopes(_) | Error::ScopesFileError(_, _) => None,
+            Error::RegexError(ref e) => Some(e),
             Error::ParseIntError(ref e) => Some(e),
             Error::VarError(ref e) => Some(e),
             Error::IOError(ref e) => Some(e),
@@ -70,6 +87,12 @@ impl From<std::io::Error> for Error {
     }
 }
 
+impl From<regex::Error> for Error {
+    fn from(err: regex::Error) -> Error {
+        Error::RegexError(err)
+    }
+}
+
 impl From<std::fmt::Error> for Error {
     fn from(err: std::fmt::Error) -> Error {
         Error::FormatError(err)
@@ -2,21 +2,142 @@
 // (C) Copyright 2023-2024 Greg Whiteley
 
 use rust_decimal::prelude::*;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 use super::{Result, Error};
 
-#[derive(Default)]
+/// Characters used to draw the bars
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// Whole '#' characters - safe for logs and pipes
+    #[default]
+    Ascii,
+    /// Unicode eighth blocks for sub-character resolution
+    Unicode,
+}
+
+/// How bar lengths relate to bucket values
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BarScale {
+    /// Bar length is proportional to the value
+    #[default]
+    Linear,
+    /// Bar length is proportional to log(value) - for long-tailed data
+    Log,
+}
+
+/// Which part of an over-long label to drop
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Truncate {
+    /// Keep the start of the label
+    #[default]
+    End,
+    /// Keep the end of the label
+    Start,
+    /// Keep the start and end of the label
+    Middle,
+}
+
+impl Truncate {
+    const ELLIPSIS: char = '…';
+
+    // Longest prefix of chars fitting in width display columns
+    fn take_width<I: Iterator<Item = char>>(chars: I, width: usize) -> Vec<char> {
+        let mut used = 0;
+        chars.take_while(|c| {
+            used += c.width().unwrap_or(0);
+            used <= width
+        }).collect()
+    }
+
+    /// Shorten s to at most width display columns, marking the dropped
+    /// part with an ellipsis
+    pub fn apply(self, s: &str, width: usize) -> String {
+        if s.width() <= width {
+            return String::from(s);
+        }
+        if width == 0 {
+            return String::new();
+        }
+        let width = width - 1; // for the ellipsis
+
+        let head_tail = match self {
+            Truncate::End => (width, 0),
+            Truncate::Start => (0, width),
+            Truncate::Middle => (width - width / 2, width / 2),
+        };
+
+        let mut out: String = Self::take_width(s.chars(), head_tail.0).into_iter().collect();
+        out.push(Self::ELLIPSIS);
+        let tail = Self::take_width(s.chars().rev(), head_tail.1);
+        out.extend(tail.into_iter().rev());
+        out
+    }
+}
+
+// Pad to width display columns
+pub(crate) fn pad_left(s: &str, width: usize) -> String {
+    format!("{}{}", " ".repeat(width.saturating_sub(s.width())), s)
+}
+
+pub(crate) fn pad_right(s: &str, width: usize) -> String {
+    format!("{}{}", s, " ".repeat(width.saturating_sub(s.width())))
+}
+
+/// Which of the text graphs Histogram renders
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Horizontal bars - draw()
+    #[default]
+    Bars,
+    /// Vertical columns - draw_vertical()
+    Columns,
+    /// Single line sparkline - draw_spark()
+    Spark,
+    /// Markdown table - draw_markdown()
+    Markdown,
+}
+
+#[derive(Default, Clone)]
 pub struct Histogram {
     buckets: Vec<(String, i64)>,
 
+    layout: Layout,
+
     width: Option<usize>,
 
+    height: Option<usize>,
+
     show_counts: bool,
+
+    show_percent: bool,
+
+    show_cumulative: bool,
+
+    style: Style,
+
+    truncate: Truncate,
+
+    bar_scale: BarScale,
+
+    spark_labels: bool,
+
+    title: Option<String>,
+    header: Option<String>,
+    show_footer: bool,
+    bucket_width: Option<Decimal>,
+
+    color: bool,
+    warn_above: Option<Decimal>,
+    crit_above: Option<Decimal>,
+    values: Vec<Decimal>,
 }
 
 impl Histogram {
 
     const COLUMNS_DEFAULT: usize = 72;
+    const ROWS_DEFAULT: usize = 20;
+    const MARKDOWN_COLUMNS: usize = 30;
 
     pub fn new<T: Into<i64> + Copy>(buckets: &[(T, &str)]) -> Histogram {
         return Self::new_it(&mut buckets.iter().map(|(x, title)| (title.to_string(), *x)));
@@ -54,6 +175,17 @@ impl Histogram {
         }
     }
 
+    /// Number of rows used for bars by draw_vertical()
+    pub fn set_height(&mut self, height: usize) -> &mut Self {
+        self.height = Some(height);
+        self
+    }
+
+    pub fn set_opt_height(&mut self, height: Option<usize>) -> &mut Self {
+        self.height = height;
+        self
+    }
+
     fn from_int_env(v: &str) -> Result<usize> {
         let v = std::env::var(v)?;
         let v = v.parse::<usize>()?;
@@ -77,6 +209,16 @@ impl Histogram {
             .map(|(Width(w),_)| w.into())
     }
 
+    #[cfg(not(feature = "terminal"))]
+    pub fn stdout_is_terminal() -> bool {
+        false
+    }
+
+    #[cfg(feature = "terminal")]
+    pub fn stdout_is_terminal() -> bool {
+        terminal_size::terminal_size().is_some()
+    }
+
     fn get_terminal_columns() -> usize {
 
         // Hmm,... terminal_size crate doesn't seem to honour COLUMNS, but does find
@@ -104,6 +246,95 @@ impl Histogram {
         self.set_show_counts(false)
     }
 
+    /// Include each bucket's percentage of the total
+    pub fn set_show_percent(&mut self, val: bool) -> &mut Self {
+        self.show_percent = val;
+        self
+    }
+
+    /// Include the running percentage of the total up to and including each bucket
+    pub fn set_show_cumulative(&mut self, val: bool) -> &mut Self {
+        self.show_cumulative = val;
+        self
+    }
+
+    /// Graph drawn when used as a Renderer
+    pub fn set_layout(&mut self, layout: Layout) -> &mut Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn set_style(&mut self, style: Style) -> &mut Self {
+        self.style = style;
+        self
+    }
+
+    /// How to shorten labels too long to fit
+    pub fn set_truncate(&mut self, truncate: Truncate) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    pub fn set_bar_scale(&mut self, bar_scale: BarScale) -> &mut Self {
+        self.bar_scale = bar_scale;
+        self
+    }
+
+    /// Surround draw_spark() output with the first and last bucket labels
+    pub fn set_spark_labels(&mut self, val: bool) -> &mut Self {
+        self.spark_labels = val;
+        self
+    }
+
+    /// Line printed above the graph
+    pub fn set_title(&mut self, title: Option<String>) -> &mut Self {
+        self.title = title;
+        self
+    }
+
+    /// Name of the value (label) column, eg "interval (s)"
+    pub fn set_header(&mut self, header: Option<String>) -> &mut Self {
+        self.header = header;
+        self
+    }
+
+    /// Summarise the scale, total samples and bucket width below the graph
+    pub fn set_show_footer(&mut self, val: bool) -> &mut Self {
+        self.show_footer = val;
+        self
+    }
+
+    /// Bucket width reported by the footer
+    pub fn set_bucket_width(&mut self, width: Option<Decimal>) -> &mut Self {
+        self.bucket_width = width;
+        self
+    }
+
+    /// Emit ANSI colour escapes
+    pub fn set_color(&mut self, val: bool) -> &mut Self {
+        self.color = val;
+        self
+    }
+
+    /// Bars for buckets with values above this are drawn yellow
+    pub fn set_warn_above(&mut self, val: Option<Decimal>) -> &mut Self {
+        self.warn_above = val;
+        self
+    }
+
+    /// Bars for buckets with values above this are drawn red
+    pub fn set_crit_above(&mut self, val: Option<Decimal>) -> &mut Self {
+        self.crit_above = val;
+        self
+    }
+
+    /// Value of each bucket, in order, compared against the colour
+    /// thresholds - buckets without a value are drawn green
+    pub fn set_values(&mut self, values: &[Decimal]) -> &mut Self {
+        self.values = values.to_vec();
+        self
+    }
+
     fn scale(v: i64, min: i64, max: i64, width: usize) -> usize {
         // Clamp v to max
         let v = v.min(max);
@@ -124,6 +355,129 @@ impl Histogram {
             .unwrap_or(width)
     }
 
+    // As scale(), but proportional to ln(1 + v)
+    fn log_scale(v: i64, min: i64, max: i64, width: usize) -> usize {
+        let v = v.clamp(min, max).abs_diff(min) as f64;
+        let delta = max.abs_diff(min) as f64;
+        if delta == 0.0 {
+            return width;
+        }
+        (v.ln_1p() * width as f64 / delta.ln_1p()).round() as usize
+    }
+
+    fn scale_by(&self, v: i64, min: i64, max: i64, width: usize) -> usize {
+        match self.bar_scale {
+            BarScale::Linear => Self::scale(v, min, max, width),
+            BarScale::Log => Self::log_scale(v, min, max, width),
+        }
+    }
+
+    // As scale_by(), but returns (whole, eighths) of a character
+    fn scale_fractional(&self, v: i64, min: i64, max: i64, width: usize) -> (usize, usize) {
+        let eighths = self.scale_by(v, min, max, width * 8);
+        (eighths / 8, eighths % 8)
+    }
+
+    // (whole, eighths) characters for bar of at least the smallest unit
+    // (we only get called for non-zero values)
+    fn bar_size(&self, v: i64, min: i64, max: i64, columns: usize) -> (usize, usize) {
+        match self.style {
+            Style::Ascii => (self.scale_by(v, min, max, columns).max(1), 0),
+            Style::Unicode => {
+                let (whole, part) = self.scale_fractional(v, min, max, columns);
+                (whole, if whole == 0 { part.max(1) } else { part })
+            },
+        }
+    }
+
+    fn bar(&self, v: i64, min: i64, max: i64, columns: usize) -> String {
+        const EIGHTHS: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+        let (whole, part) = self.bar_size(v, min, max, columns);
+        match self.style {
+            Style::Ascii => "#".repeat(whole),
+            Style::Unicode => {
+                let mut bar = "█".repeat(whole);
+                if part > 0 {
+                    bar.push(EIGHTHS[part]);
+                }
+                bar
+            },
+        }
+    }
+
+    // Bar extending left from the axis - whole characters only as there
+    // are no right-aligned partial blocks
+    fn negative_bar(&self, v: i64, min: i64, columns: usize) -> String {
+        let count = self.scale_by(v.saturating_neg(), 0, min.saturating_neg(), columns).max(1);
+        match self.style {
+            Style::Ascii => "#".repeat(count),
+            Style::Unicode => "█".repeat(count),
+        }
+    }
+
+    fn scale_hint(&self) -> Option<&'static str> {
+        match self.bar_scale {
+            BarScale::Linear => None,
+            BarScale::Log => Some("(log scale)"),
+        }
+    }
+
+    // Describe what a whole bar character represents, plus sample and bucket totals
+    fn footer(&self, min: i64, max: i64, width: usize) -> Option<String> {
+        if !self.show_footer {
+            return self.scale_hint().map(String::from);
+        }
+
+        let mut parts = vec![];
+        match self.bar_scale {
+            BarScale::Linear => {
+                let symbol = match self.style {
+                    Style::Ascii => '#',
+                    Style::Unicode => '█',
+                };
+                let per_char = max.abs_diff(min) as f64 / width.max(1) as f64;
+                // keep a couple of significant digits for sparse data
+                let precision = if per_char >= 10.0 { 0 } else if per_char >= 1.0 { 1 } else { 2 };
+                let per_char = format!("{:.precision$}", per_char);
+                let per_char = if per_char.contains('.') {
+                    per_char.trim_end_matches('0').trim_end_matches('.')
+                } else {
+                    &per_char
+                };
+                parts.push(format!("{} ≈ {} events", symbol, per_char));
+            },
+            BarScale::Log => parts.push(String::from("log scale")),
+        }
+        parts.push(format!("{} samples", self.total()));
+        if let Some(width) = self.bucket_width {
+            parts.push(format!("bucket width {}", width));
+        }
+        Some(parts.join(", "))
+    }
+
+    const RESET: &'static str = "\x1b[0m";
+    const DIM: &'static str = "\x1b[2m";
+
+    fn bar_color(&self, ix: usize) -> &'static str {
+        let v = self.values.get(ix);
+        let above = |threshold: Option<Decimal>| matches!((v, threshold), (Some(v), Some(t)) if *v > t);
+        if above(self.crit_above) {
+            "\x1b[31m" // red
+        } else if above(self.warn_above) {
+            "\x1b[33m" // yellow
+        } else {
+            "\x1b[32m" // green
+        }
+    }
+
+    fn paint(&self, ix: usize, bar: &str) -> String {
+        if self.color && !bar.is_empty() {
+            format!("{}{}{}", self.bar_color(ix), bar, Self::RESET)
+        } else {
+            String::from(bar)
+        }
+    }
+
     fn count_size(&self) -> usize {
         if self.show_counts {
             if let Some(first) = self.buckets.first() {
@@ -141,6 +495,44 @@ impl Histogram {
         0
     }
 
+    fn total(&self) -> i64 {
+        self.buckets.iter().map(|(_, v)| v).sum()
+    }
+
+    fn format_percent(v: i64, total: i64) -> String {
+        let mut percent = Bucket::percent_of(v, total).round_dp(1);
+        percent.rescale(1);
+        format!("{}%", percent)
+    }
+
+    fn percent_column(&self) -> Vec<String> {
+        if !self.show_percent {
+            return vec![];
+        }
+        let total = self.total();
+        self.buckets.iter()
+            .map(|(_, v)| Self::format_percent(*v, total))
+            .collect()
+    }
+
+    fn cumulative_column(&self) -> Vec<String> {
+        if !self.show_cumulative {
+            return vec![];
+        }
+        let total = self.total();
+        let mut sum = 0;
+        self.buckets.iter()
+            .map(|(_, v)| {
+                sum += v;
+                Self::format_percent(sum, total)
+            })
+            .collect()
+    }
+
+    fn column_size(column: &[String]) -> usize {
+        column.iter().map(|x| x.len()).max().unwrap_or(0)
+    }
+
     pub fn draw(&self) -> Result<String> {
         use std::{fmt::Write, ops::Div};
 
@@ -150,14 +542,15 @@ impl Histogram {
         let mut min_val = i64::MAX;
         let mut max_val = i64::MIN;
         for (name, v) in &self.buckets {
-            max_name_len = max_name_len.max(name.len());
+            max_name_len = max_name_len.max(name.width());
             min_val = min_val.min(*v);
             max_val = max_val.max(*v);
         }
 
-        let zero_base = true; // TODO - derive this
-
-        if zero_base && min_val > 0 {
+        // Bars grow from zero, unless there are negative values in which
+        // case they diverge either side of a zero axis
+        let zero_base = min_val >= 0;
+        if zero_base {
             min_val = 0;
         }
 
@@ -169,41 +562,478 @@ impl Histogram {
         let name_field_len = max_name_len + 1;
 
         let count_field_len = self.count_size();
-        let count_field_allowance = if count_field_len > 0 {
-            count_field_len + 2 // colon, space
-        } else { count_field_len };
+        let percents = self.percent_column();
+        let percent_field_len = Self::column_size(&percents);
+        let cumulatives = self.cumulative_column();
+        let cumulative_field_len = Self::column_size(&cumulatives);
+
+        let field_allowance: usize = [count_field_len, percent_field_len, cumulative_field_len].iter()
+            .filter(|len| **len > 0)
+            .map(|len| len + 1) // space
+            .sum();
+        let field_allowance = if field_allowance > 0 {
+            field_allowance + 1 // colon
+        } else { field_allowance };
 
         let columns = term_columns
-            .saturating_sub(name_field_len + 1 + count_field_allowance);
+            .saturating_sub(name_field_len + 1 + field_allowance);
 
-        if columns == 0 {
+        // diverging bars need a column either side of the axis
+        if columns == 0 || (!zero_base && columns < 3) {
             return Err(Error::DataTagsTooLongToFitTerminal(term_columns));
         }
 
-        // generate template of max required number of #'s
-        let template = "#".repeat(columns);
+        // Split the bar columns either side of the axis at zero's place in the range
+        let (negative_columns, positive_columns) = if zero_base {
+            (0, columns)
+        } else {
+            let columns = columns - 1; // axis
+            let negative_columns = Self::scale(0, min_val, max_val.max(0), columns)
+                .clamp(1, columns - 1);
+            (negative_columns, columns - negative_columns)
+        };
 
-        for (name, v) in &self.buckets {
-            let count = Self::scale(*v, min_val, max_val, columns);
+        if let Some(title) = &self.title {
+            writeln!(buf, "{}", title)?;
+        }
+        if let Some(header) = &self.header {
+            writeln!(buf, "{}", pad_left(header, max_name_len))?;
+        }
 
-            write!(buf, "{:>max_name_len$}", &name[0..max_name_len.min(name.len())])?;
+        for (ix, (name, v)) in self.buckets.iter().enumerate() {
+            let empty = *v == 0;
+            let dim = self.color && empty;
+            if dim {
+                buf.push_str(Self::DIM);
+            }
+
+            write!(buf, "{}", pad_left(&self.truncate.apply(name, max_name_len), max_name_len))?;
 
+            if field_allowance > 0 {
+                buf.push(':');
+            }
             if self.show_counts {
-                write!(buf, ": {:>count_field_len$}", v)?;
+                write!(buf, " {:>count_field_len$}", v)?;
+            }
+            if let Some(percent) = percents.get(ix) {
+                write!(buf, " {:>percent_field_len$}", percent)?;
+            }
+            if let Some(cumulative) = cumulatives.get(ix) {
+                write!(buf, " {:>cumulative_field_len$}", cumulative)?;
+            }
+
+            if dim {
+                buf.push_str(Self::RESET);
             }
 
-            // if value is literally zero against a zero base don't print anything (round to nothing)
+            // if value is literally zero don't print anything (round to nothing)
             // otherwise it will always round to _at least one_
-            if *v == 0 && zero_base {
-                writeln!(buf)?;
+            let bar = match v.cmp(&0) {
+                std::cmp::Ordering::Greater => self.bar(*v, 0, max_val, positive_columns),
+                std::cmp::Ordering::Less => self.negative_bar(*v, min_val, negative_columns),
+                std::cmp::Ordering::Equal => String::new(),
+            };
+
+            if zero_base {
+                if empty {
+                    writeln!(buf)?;
+                } else {
+                    writeln!(buf, " {}", self.paint(ix, &bar))?;
+                }
+            } else if *v < 0 {
+                writeln!(buf, " {}{}|", " ".repeat(negative_columns.saturating_sub(bar.width())), self.paint(ix, &bar))?;
             } else {
-                let count = count.max(1); // always print at least one if we aren't zero
-                writeln!(buf, " {}", &template[0..count])?;
+                writeln!(buf, " {}|{}", " ".repeat(negative_columns), self.paint(ix, &bar))?;
             }
         }
 
+        if let Some(footer) = self.footer(min_val, max_val, columns) {
+            // under the start of the bars
+            writeln!(buf, "{}{}", " ".repeat(name_field_len + field_allowance), footer)?;
+        }
+
+        Ok(buf)
+    }
+
+    // Lay labels out under columns of width col - on one row if they fit,
+    // otherwise staggered over two rows, or failing that rotated with one
+    // character per row.
+    fn write_axis_labels(buf: &mut String, labels: &[String], col: usize) -> Result<()> {
+        use std::fmt::Write;
+
+        let len = labels.iter().map(|l| l.width()).max().unwrap_or(0);
+
+        let mut rows: Vec<String> = vec![];
+        if len < col {
+            let mut line = String::new();
+            for l in labels {
+                line.push_str(&pad_right(l, col));
+            }
+            rows.push(line);
+        } else if len < 2 * col {
+            for stagger in 0..2 {
+                let mut line = String::new();
+                for (ix, l) in labels.iter().enumerate().skip(stagger).step_by(2) {
+                    line.push_str(&" ".repeat((ix * col).saturating_sub(line.width())));
+                    line.push_str(l);
+                }
+                rows.push(line);
+            }
+        } else {
+            for row in 0..len {
+                let mut line = String::new();
+                for l in labels {
+                    let c = l.chars().nth(row).unwrap_or(' ');
+                    line.push_str(&pad_right(&c.to_string(), col));
+                }
+                rows.push(line);
+            }
+        }
+
+        for row in rows {
+            writeln!(buf, "{}", row.trim_end())?;
+        }
+        Ok(())
+    }
+
+    /// Draw as vertical columns, with the labels along the x-axis
+    pub fn draw_vertical(&self) -> Result<String> {
+        use std::fmt::Write;
+        const LOWER_EIGHTHS: [char; 8] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇'];
+
+        let mut buf = String::new();
+
+        let term_columns = self.width
+            .unwrap_or(Self::COLUMNS_DEFAULT);
+        let rows = self.height
+            .unwrap_or(Self::ROWS_DEFAULT);
+
+        // no more than half the size (but allow for rotated labels in narrow graphs)
+        let max_name_len = (term_columns / 2).max(8);
+        let labels: Vec<String> = self.buckets.iter()
+            .map(|(name, _)| self.truncate.apply(name, max_name_len))
+            .collect();
+
+        // columns grow up from a zero base, with no room for negative values
+        if self.buckets.iter().any(|(_, v)| *v < 0) {
+            return Err(Error::UnsupportedOutput(String::from("columns")));
+        }
+        let min_val = 0;
+        let max_val = self.buckets.iter().map(|(_, v)| *v).max().unwrap_or(0);
+
+        let percents = self.percent_column();
+        let cumulatives = self.cumulative_column();
+
+        // Give each column room for its label if we can, otherwise as
+        // much as we can
+        let wanted = labels.iter().map(|l| l.width())
+            .chain(std::iter::once(self.count_size()))
+            .chain(std::iter::once(Self::column_size(&percents)))
+            .chain(std::iter::once(Self::column_size(&cumulatives)))
+            .max()
+            .unwrap_or(0) + 1;
+        let col = if self.buckets.len() * wanted <= term_columns {
+            wanted
+        } else {
+            term_columns / self.buckets.len().max(1)
+        };
+
+        if col < 2 || rows == 0 {
+            return Err(Error::DataTagsTooLongToFitTerminal(term_columns));
+        }
+        let bar_width = col - 1;
+
+        // (whole, eighths) of each column's height
+        let heights: Vec<(usize, usize)> = self.buckets.iter()
+            .map(|(_, v)| {
+                if *v == 0 {
+                    (0, 0)
+                } else {
+                    self.bar_size(*v, min_val, max_val, rows)
+                }
+            })
+            .collect();
+
+        if let Some(title) = &self.title {
+            writeln!(buf, "{}", title)?;
+        }
+
+        for row in (0..rows).rev() {
+            let mut line = String::new();
+            for (ix, (whole, part)) in heights.iter().enumerate() {
+                let c = if row < *whole {
+                    match self.style {
+                        Style::Ascii => '#',
+                        Style::Unicode => '█',
+                    }
+                } else if row == *whole && *part > 0 {
+                    LOWER_EIGHTHS[*part]
+                } else {
+                    ' '
+                };
+
+                let cell = c.to_string().repeat(bar_width);
+                if self.color && c != ' ' {
+                    write!(line, "{}{}{} ", self.bar_color(ix), cell, Self::RESET)?;
+                } else {
+                    write!(line, "{} ", cell)?;
+                }
+            }
+            writeln!(buf, "{}", line.trim_end())?;
+        }
+
+        writeln!(buf, "{}", "-".repeat(col * self.buckets.len() - 1))?;
+        Self::write_axis_labels(&mut buf, &labels, col)?;
+
+        if self.show_counts {
+            let counts: Vec<String> = self.buckets.iter()
+                .map(|(_, v)| v.to_string())
+                .collect();
+            Self::write_axis_labels(&mut buf, &counts, col)?;
+        }
+        if !percents.is_empty() {
+            Self::write_axis_labels(&mut buf, &percents, col)?;
+        }
+        if !cumulatives.is_empty() {
+            Self::write_axis_labels(&mut buf, &cumulatives, col)?;
+        }
+
+        if let Some(header) = &self.header {
+            writeln!(buf, "{}", header)?;
+        }
+        if let Some(footer) = self.footer(min_val, max_val, rows) {
+            writeln!(buf, "{}", footer)?;
+        }
+
+        Ok(buf)
+    }
+
+    /// Draw as a markdown table of label, count, percent and a bar.
+    /// The bar has a fixed width narrow enough for rendered markdown
+    /// rather than following the terminal width.
+    pub fn draw_markdown(&self) -> Result<String> {
+        use std::fmt::Write;
+
+        fn cell(s: &str) -> String {
+            s.replace('\\', "\\\\").replace('|', "\\|")
+        }
+
+        let columns = Self::MARKDOWN_COLUMNS;
+        let max_val = self.buckets.iter().map(|(_, v)| *v).max().unwrap_or(0);
+        let total = self.total();
+
+        let mut buf = String::new();
+        if let Some(title) = &self.title {
+            writeln!(buf, "**{}**", cell(title))?;
+            writeln!(buf)?;
+        }
+        writeln!(buf, "| {} | count | percent | distribution |", cell(self.header.as_deref().unwrap_or("value")))?;
+        writeln!(buf, "|---:|---:|---:|:---|")?;
+        for (name, v) in &self.buckets {
+            let bar = if *v > 0 { self.bar(*v, 0, max_val, columns) } else { String::new() };
+            writeln!(buf, "| {} | {} | {} | {} |", cell(name), v, Self::format_percent(*v, total), bar)?;
+        }
         Ok(buf)
     }
+
+    /// Draw the whole distribution on a single line, one character per bucket
+    pub fn draw_spark(&self) -> Result<String> {
+        use std::fmt::Write;
+        const LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+        // levels rise from a zero base, with no room for negative values
+        if self.buckets.iter().any(|(_, v)| *v < 0) {
+            return Err(Error::UnsupportedOutput(String::from("spark")));
+        }
+        let min_val = 0;
+        let max_val = self.buckets.iter().map(|(_, v)| *v).max().unwrap_or(0);
+
+        let mut buf = String::new();
+        if self.spark_labels {
+            if let Some((first, _)) = self.buckets.first() {
+                write!(buf, "{} ", first)?;
+            }
+        }
+        for (ix, (_, v)) in self.buckets.iter().enumerate() {
+            if *v == 0 {
+                buf.push(' ');
+                continue;
+            }
+            let level = self.scale_by(*v, min_val, max_val, LEVELS.len()).clamp(1, LEVELS.len());
+            if self.color {
+                write!(buf, "{}{}{}", self.bar_color(ix), LEVELS[level - 1], Self::RESET)?;
+            } else {
+                buf.push(LEVELS[level - 1]);
+            }
+        }
+        if self.spark_labels {
+            if let Some((last, _)) = self.buckets.last() {
+                write!(buf, " {}", last)?;
+            }
+        }
+        writeln!(buf)?;
+        Ok(buf)
+    }
+}
+
+/// One bucket of a histogram, with its value range where known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bucket {
+    pub label: String,
+    pub lower: Option<Decimal>,
+    pub upper: Option<Decimal>,
+    pub count: i64,
+}
+
+impl Bucket {
+    /// Percentage of total that count is - unrounded, so the text and
+    /// data outputs can each round it to their own precision
+    pub fn percent_of(count: i64, total: i64) -> Decimal {
+        if total == 0 {
+            return Decimal::ZERO;
+        }
+        Decimal::from(count) * Decimal::ONE_HUNDRED / Decimal::from(total)
+    }
+
+    /// Bucket of categorical (eg string) data
+    pub fn labelled(label: String, count: i64) -> Self {
+        Bucket { label, lower: None, upper: None, count }
+    }
+
+    /// Bucket of a value range, labelled with its upper bound
+    pub fn range(lower: Decimal, upper: Decimal, count: i64) -> Self {
+        Bucket { label: upper.to_string(), lower: Some(lower), upper: Some(upper), count }
+    }
+}
+
+/// Time spent at each number of open scopes, from a timeline of the
+/// (time, open scopes) at each change, ending at the last time seen.
+/// Times are counted in units of unit to fit Bucket::count, or by default
+/// of the finest resolution of the timeline, eg 0.01s.  Returns the
+/// buckets and the unit used.
+pub fn concurrency_levels(timeline: &[(Decimal, usize)], unit: Option<Decimal>) -> (Vec<Bucket>, Decimal) {
+    let scale = timeline.iter().map(|(time, _)| time.scale()).max().unwrap_or(0);
+    let unit = unit
+        .filter(|unit| *unit > Decimal::ZERO)
+        .unwrap_or_else(|| Decimal::new(1, scale));
+    let max_level = match timeline.iter().map(|(_, level)| *level).max() {
+        Some(max_level) => max_level,
+        None => return (vec![], unit),
+    };
+    let mut spent = vec![Decimal::ZERO; max_level + 1];
+    for change in timeline.windows(2) {
+        spent[change[0].1] += change[1].0 - change[0].0;
+    }
+    let buckets = spent.into_iter()
+        .enumerate()
+        .map(|(level, time)| {
+            let count = (time / unit).round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero).to_i64().unwrap_or(i64::MAX);
+            Bucket::labelled(level.to_string(), count)
+        })
+        .collect();
+    (buckets, unit)
+}
+
+/// Highest number of open scopes in each window of width delta, from a
+/// timeline of the (time, open scopes) at each change
+pub fn concurrency_peaks(timeline: &[(Decimal, usize)], delta: Decimal) -> Vec<Bucket> {
+    let (first, last) = match (timeline.first(), timeline.last()) {
+        (Some(first), Some(last)) if delta > Decimal::ZERO => (first.0, last.0),
+        _ => return vec![],
+    };
+    let mut buckets = vec![];
+    let mut changes = timeline.iter().peekable();
+    let mut level = 0;
+    let mut lower = (first / delta).floor() * delta;
+    while lower <= last {
+        let upper = lower + delta;
+        // open at the start of the window
+        while let Some((_, open)) = changes.next_if(|(time, _)| *time <= lower) {
+            level = *open;
+        }
+        let mut peak = level;
+        while let Some((_, open)) = changes.next_if(|(time, _)| *time < upper) {
+            level = *open;
+            peak = peak.max(level);
+        }
+        buckets.push(Bucket::range(lower, upper, peak as i64));
+        lower = upper;
+    }
+    buckets
+}
+
+/// Count and percentiles of a set of values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub count: usize,
+    pub p50: Decimal,
+    pub p99: Decimal,
+}
+
+impl Stats {
+    /// None if there are no values
+    pub fn new(values: &[Decimal]) -> Option<Stats> {
+        let mut sorted = values.to_vec();
+        sorted.sort();
+        Some(Stats {
+            count: sorted.len(),
+            p50: Self::percentile(&sorted, 50)?,
+            p99: Self::percentile(&sorted, 99)?,
+        })
+    }
+
+    // Nearest-rank percentile - always one of the values
+    fn percentile(sorted: &[Decimal], p: usize) -> Option<Decimal> {
+        let rank = (p * sorted.len() + 99) / 100;
+        sorted.get(rank.max(1) - 1).copied()
+    }
+}
+
+/// Writes bucket data in some output format.
+///
+/// Implemented by Histogram for the text graphs and by the types in
+/// `output` for the machine readable and chart formats.  A Histogram
+/// renders the buckets passed in, not those it was constructed with.
+///
+/// Only covers output of a single list of buckets - OpenMetrics series,
+/// `output::StatsTable` and the `tree::Frame` reports have their own
+/// writers as their data has a different shape.
+pub trait Renderer {
+    fn render(&self, buckets: &[Bucket], w: &mut dyn std::fmt::Write) -> Result<()>;
+
+    /// Render to a byte stream, eg stdout or a file
+    fn render_io(&self, buckets: &[Bucket], w: &mut dyn std::io::Write) -> Result<()> {
+        w.write_all(self.render_to_string(buckets)?.as_bytes())?;
+        Ok(())
+    }
+
+    fn render_to_string(&self, buckets: &[Bucket]) -> Result<String> {
+        let mut out = String::new();
+        self.render(buckets, &mut out)?;
+        Ok(out)
+    }
+}
+
+impl Renderer for Histogram {
+    /// Draws `buckets` with this histogram's settings and layout - any
+    /// buckets the histogram was constructed with are ignored
+    fn render(&self, buckets: &[Bucket], w: &mut dyn std::fmt::Write) -> Result<()> {
+        let mut histogram = self.clone();
+        histogram.buckets = buckets.iter().map(|b| (b.label.clone(), b.count)).collect();
+        histogram.values = buckets.iter().map_while(|b| b.upper).collect();
+        if histogram.bucket_width.is_none() {
+            histogram.bucket_width = buckets.first()
+                .and_then(|b| Some(b.upper? - b.lower?));
+        }
+        let out = match self.layout {
+            Layout::Bars => histogram.draw(),
+            Layout::Columns => histogram.draw_vertical(),
+            Layout::Spark => histogram.draw_spark(),
+            Layout::Markdown => histogram.draw_markdown(),
+        }?;
+        w.write_str(&out)?;
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -242,6 +1072,18 @@ impl Buckets {
         self
     }
 
+    /// Width of each bucket - only known after analyse()
+    pub fn width(&self) -> Option<Decimal> {
+        if self.delta.is_some() {
+            return self.delta;
+        }
+        match (self.min, self.max) {
+            (Some(min), Some(max)) if self.count > 0 =>
+                Some(((max - min) / Decimal::new(self.count as i64, 0)).normalize()),
+            _ => None,
+        }
+    }
+
     fn linear_buckets(&self) -> Vec<(Decimal, Decimal)> {
         let span = self.max.expect("max not set") - self.min.expect("min not set");
         let delta = span / Decimal::new(self.count as i64, 0);
@@ -256,6 +1098,14 @@ impl Buckets {
         }).collect()
     }
 
+    /// As generate(), but including each bucket's range
+    pub fn generate_buckets(&self, v: &[Decimal]) -> Vec<Bucket> {
+        let counts = self.generate(v);
+        self.linear_buckets().into_iter()
+            .map(|(lower, upper)| Bucket::range(lower, upper, counts[&upper]))
+            .collect()
+    }
+
     pub fn generate(&self, v: &[Decimal]) -> std::collections::BTreeMap<Decimal, i64> {
         let mut map = std::collections::BTreeMap::new();
         let buckets = self.linear_buckets();
@@ -299,7 +1149,7 @@ mod tests {
     #[test]
     fn test_draw() {
         // TODO - not a test
-        let s = Histogram::new_indexed(&vec![100, 200, 300, 400, 200, 100]).draw().unwrap();
+        let s = Histogram::new_indexed(&[100, 200, 300, 400, 200, 100]).draw().unwrap();
         println!("{}", s);
         assert_eq!(s, r#"       0 ###############
        1 ###############################
@@ -309,7 +1159,7 @@ mod tests {
        5 ###############
 "#);
 
-        let h = Histogram::new(&vec![(100, "1-5"), (200, "6-10"), (300, "11-15"), (400, "16-20"), (200, "21-25"), (100, "25-30"), (0, "31-35")]);
+        let h = Histogram::new(&[(100, "1-5"), (200, "6-10"), (300, "11-15"), (400, "16-20"), (200, "21-25"), (100, "25-30"), (0, "31-35")]);
         let s = h.draw().unwrap();
         println!("{}", s);
         assert_eq!(s, r#"     1-5 ###############
@@ -323,7 +1173,7 @@ mod tests {
  );
 
         // ensure non-zero doesn't get crushed to zero
-        let h = Histogram::new(&vec![(100, "1-5"), (200, "6-10"), (300, "11-15"), (400, "16-20"), (200, "21-25"), (100, "25-30"), (1, "31-35")]);
+        let h = Histogram::new(&[(100, "1-5"), (200, "6-10"), (300, "11-15"), (400, "16-20"), (200, "21-25"), (100, "25-30"), (1, "31-35")]);
         let s = h.draw().unwrap();
         println!("{}", s);
         assert_eq!(s, r#"     1-5 ###############
@@ -337,7 +1187,7 @@ mod tests {
  );
 
         // ensure non-zero doesn't get crushed to zero
-        let s = Histogram::new(&vec![
+        let s = Histogram::new(&[
             (100, "1-5"),
             (200, "6-10"),
             (300, "11-15"),
@@ -360,6 +1210,364 @@ mod tests {
  );
     }
 
+    #[test]
+    fn test_percent() {
+        assert_eq!(Bucket::percent_of(1, 3).round_dp(4), Decimal::new(333333, 4));
+        assert_eq!(Bucket::percent_of(1, 0), Decimal::ZERO);
+        // the text graphs round the same value as the data outputs
+        assert_eq!(Histogram::format_percent(1, 3), "33.3%");
+        assert_eq!(Histogram::format_percent(1, 2000), "0.0%");
+        assert_eq!(Histogram::format_percent(2, 2), "100.0%");
+        assert_eq!(Histogram::format_percent(0, 0), "0.0%");
+    }
+
+    #[test]
+    fn test_draw_percent() {
+        let s = Histogram::new(&[(1, "a"), (2, "b"), (5, "c"), (0, "d"), (2, "e")])
+            .set_width(40)
+            .show_counts()
+            .set_show_percent(true)
+            .set_show_cumulative(true)
+            .draw()
+            .unwrap();
+        println!("{}", s);
+        assert_eq!(s, r#"       a: 1 10.0%  10.0% ##
+       b: 2 20.0%  30.0% #####
+       c: 5 50.0%  80.0% ##############
+       d: 0  0.0%  80.0%
+       e: 2 20.0% 100.0% #####
+"#);
+
+        let s = Histogram::new(&[(1, "a"), (3, "b")])
+            .set_width(26)
+            .set_show_cumulative(true)
+            .draw()
+            .unwrap();
+        println!("{}", s);
+        assert_eq!(s, r#"       a:  25.0% ##
+       b: 100.0% ########
+"#);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(Truncate::End.apply("abcdef", 6), "abcdef");
+        assert_eq!(Truncate::End.apply("abcdef", 4), "abc…");
+        assert_eq!(Truncate::Start.apply("abcdef", 4), "…def");
+        assert_eq!(Truncate::Middle.apply("abcdef", 4), "ab…f");
+        assert_eq!(Truncate::Middle.apply("abcdefg", 5), "ab…fg");
+        assert_eq!(Truncate::End.apply("abcdef", 0), "");
+
+        // multibyte and double-width characters
+        assert_eq!(Truncate::End.apply("ééééé", 3), "éé…");
+        assert_eq!(Truncate::Start.apply("日本語テキスト", 6), "…スト");
+    }
+
+    #[test]
+    fn test_draw_unicode_labels() {
+        let s = Histogram::new(&[(1, "日本語"), (2, "ascii"), (2, "éééééééé-éééééééééé")])
+            .set_width(30)
+            .set_truncate(Truncate::Middle)
+            .draw()
+            .unwrap();
+        println!("{}", s);
+        assert_eq!(s, r#"         日本語 ######
+          ascii #############
+ééééééé…ééééééé #############
+"#);
+    }
+
+    #[test]
+    fn test_draw_unicode() {
+        let s = Histogram::new(&[(100, "a"), (105, "b"), (110, "c"), (1, "d"), (0, "e")])
+            .set_width(20)
+            .set_style(Style::Unicode)
+            .draw()
+            .unwrap();
+        println!("{}", s);
+        assert_eq!(s, r#"       a █████████
+       b █████████▌
+       c ██████████
+       d ▏
+       e
+"#);
+    }
+
+    #[test]
+    fn test_draw_color() {
+        // thresholds compare the bucket values, not the counts
+        let s = Histogram::new(&[(30, "a"), (20, "b"), (10, "c"), (0, "d")])
+            .set_width(20)
+            .set_color(true)
+            .set_values(&[Decimal::from(10), Decimal::from(20), Decimal::from(30), Decimal::from(40)])
+            .set_warn_above(Some(Decimal::from(10)))
+            .set_crit_above(Some(Decimal::from(20)))
+            .draw()
+            .unwrap();
+        println!("{}", s);
+        assert_eq!(s, "       a \x1b[32m##########\x1b[0m
+       b \x1b[33m#######\x1b[0m
+       c \x1b[31m###\x1b[0m
+\x1b[2m       d\x1b[0m
+");
+
+        // as a Renderer the values are the buckets' upper bounds
+        let buckets: Vec<Bucket> = [(1, 5), (2, 1), (3, 1)].iter()
+            .map(|&(upper, count)| Bucket {
+                label: upper.to_string(),
+                lower: Some(Decimal::from(upper - 1)),
+                upper: Some(Decimal::from(upper)),
+                count,
+            })
+            .collect();
+        let mut s = String::new();
+        Histogram::default()
+            .set_width(20)
+            .set_color(true)
+            .set_warn_above(Some(Decimal::from(1)))
+            .set_crit_above(Some(Decimal::from(2)))
+            .render(&buckets, &mut s)
+            .unwrap();
+        println!("{}", s);
+        assert_eq!(s, "       1 \x1b[32m##########\x1b[0m
+       2 \x1b[33m##\x1b[0m
+       3 \x1b[31m##\x1b[0m
+");
+
+        // no thresholds - everything is green
+        let s = Histogram::new(&[(10, "a"), (20, "b")])
+            .set_width(20)
+            .set_color(true)
+            .draw()
+            .unwrap();
+        assert_eq!(s, "       a \x1b[32m#####\x1b[0m
+       b \x1b[32m##########\x1b[0m
+");
+    }
+
+    #[test]
+    fn test_draw_vertical() {
+        let s = Histogram::new(&[(1, "a"), (3, "b"), (4, "c"), (0, "d")])
+            .set_height(4)
+            .show_counts()
+            .draw_vertical()
+            .unwrap();
+        println!("{}", s);
+        assert_eq!(s, r#"    #
+  # #
+  # #
+# # #
+-------
+a b c d
+1 3 4 0
+"#);
+
+        // staggered labels
+        let s = Histogram::new(&[(1, "1000"), (2, "2000"), (2, "3000")])
+            .set_height(2)
+            .set_width(9)
+            .draw_vertical()
+            .unwrap();
+        println!("{}", s);
+        assert_eq!(s, r#"   ## ##
+## ## ##
+--------
+1000  3000
+   2000
+"#);
+
+        // rotated labels
+        let s = Histogram::new(&[(1, "1000"), (2, "2000"), (2, "3000")])
+            .set_height(2)
+            .set_width(6)
+            .draw_vertical()
+            .unwrap();
+        println!("{}", s);
+        assert_eq!(s, r#"  # #
+# # #
+-----
+1 2 3
+0 0 0
+0 0 0
+0 0 0
+"#);
+
+        let s = Histogram::new(&[(8, "a"), (3, "b"), (1, "c")])
+            .set_height(1)
+            .set_style(Style::Unicode)
+            .draw_vertical()
+            .unwrap();
+        assert_eq!(s, "█ ▃ ▁\n-----\na b c\n");
+    }
+
+    #[test]
+    fn test_draw_signed() {
+        let s = Histogram::new(&[(-10, "a"), (-1, "b"), (0, "c"), (5, "d"), (20, "e")])
+            .set_width(40)
+            .show_counts()
+            .draw()
+            .unwrap();
+        println!("{}", s);
+        assert_eq!(s, r#"       a: -10 ########|
+       b:  -1        #|
+       c:   0         |
+       d:   5         |####
+       e:  20         |################
+"#);
+
+        // all negative
+        let s = Histogram::new(&[(-4, "a"), (-2, "b")])
+            .set_width(20)
+            .draw()
+            .unwrap();
+        println!("{}", s);
+        assert_eq!(s, r#"       a ########|
+       b     ####|
+"#);
+
+        // a tiny negative still gets a column beside a large positive
+        let s = Histogram::new(&[(-1, "a"), (1000, "b"), (500, "c")])
+            .set_width(40)
+            .draw()
+            .unwrap();
+        println!("{}", s);
+        assert_eq!(s, r#"       a #|
+       b  |############################
+       c  |##############
+"#);
+    }
+
+    #[test]
+    fn test_draw_signed_unsupported() {
+        let h = Histogram::new(&[(-1, "a"), (2, "b")]);
+        assert!(matches!(h.draw_vertical(), Err(Error::UnsupportedOutput(_))));
+        assert!(matches!(h.draw_spark(), Err(Error::UnsupportedOutput(_))));
+    }
+
+    #[test]
+    fn test_draw_markdown() {
+        let s = Histogram::new(&[(1, "a|b"), (4, "c"), (0, "d")])
+            .set_header(Some(String::from("key")))
+            .set_title(Some(String::from("Title")))
+            .draw_markdown()
+            .unwrap();
+        println!("{}", s);
+        assert_eq!(s, r#"**Title**
+
+| key | count | percent | distribution |
+|---:|---:|---:|:---|
+| a\|b | 1 | 20.0% | ####### |
+| c | 4 | 80.0% | ############################## |
+| d | 0 | 0.0% |  |
+"#);
+    }
+
+    #[test]
+    fn test_draw_spark() {
+        let mut h = Histogram::new(&[(1, "100"), (4, "200"), (8, "300"), (0, "400"), (2, "500")]);
+        assert_eq!(h.draw_spark().unwrap(), "▁▄█ ▂\n");
+        assert_eq!(h.set_spark_labels(true).draw_spark().unwrap(), "100 ▁▄█ ▂ 500\n");
+    }
+
+    #[test]
+    fn test_renderer() {
+        let buckets: Vec<Bucket> = [(1, "100"), (4, "200"), (8, "300"), (0, "400"), (2, "500")].iter()
+            .map(|(c, l)| Bucket::labelled(String::from(*l), *c))
+            .collect();
+        let mut h = Histogram::new(&[(1, "ignored")]);
+        h.set_layout(Layout::Spark);
+        assert_eq!(h.render_to_string(&buckets).unwrap(), "▁▄█ ▂\n");
+
+        // bucket width comes from the bucket ranges
+        let buckets = vec![
+            Bucket::range(Decimal::new(0, 0), Decimal::new(100, 0), 10),
+            Bucket::range(Decimal::new(100, 0), Decimal::new(200, 0), 60),
+        ];
+        let s = Histogram::default()
+            .set_width(30)
+            .set_show_footer(true)
+            .render_to_string(&buckets)
+            .unwrap();
+        println!("{}", s);
+        assert_eq!(s, r#"     100 ###
+     200 ####################
+         # ≈ 3 events, 70 samples, bucket width 100
+"#);
+    }
+
+    #[test]
+    fn test_draw_log() {
+        let s = Histogram::new(&[(1000, "heartbeat"), (10, "error"), (1, "warn"), (0, "none")])
+            .set_width(32)
+            .set_bar_scale(BarScale::Log)
+            .draw()
+            .unwrap();
+        println!("{}", s);
+        assert_eq!(s, r#"heartbeat #####################
+    error #######
+     warn ##
+     none
+          (log scale)
+"#);
+    }
+
+    #[test]
+    fn test_draw_legend() {
+        let s = Histogram::new(&[(10, "100"), (60, "200"), (0, "300")])
+            .set_width(30)
+            .show_counts()
+            .set_title(Some(String::from("time-diff example.txt")))
+            .set_header(Some(String::from("interval (s)")))
+            .set_show_footer(true)
+            .set_bucket_width(Some(Decimal::new(100, 0)))
+            .draw()
+            .unwrap();
+        println!("{}", s);
+        assert_eq!(s, r#"time-diff example.txt
+interval (s)
+     100: 10 ##
+     200: 60 ################
+     300:  0
+             # ≈ 3.8 events, 70 samples, bucket width 100
+"#);
+
+        let s = Histogram::new(&[(1, "a"), (2, "b")])
+            .set_height(2)
+            .set_title(Some(String::from("title")))
+            .set_header(Some(String::from("key")))
+            .set_show_footer(true)
+            .draw_vertical()
+            .unwrap();
+        println!("{}", s);
+        assert_eq!(s, r#"title
+  #
+# #
+---
+a b
+key
+# ≈ 1 events, 3 samples
+"#);
+    }
+
+    #[test]
+    fn test_log_scale() {
+        assert_eq!(Histogram::log_scale(0, 0, 999, 30), 0);
+        assert_eq!(Histogram::log_scale(9, 0, 999, 30), 10);
+        assert_eq!(Histogram::log_scale(99, 0, 999, 30), 20);
+        assert_eq!(Histogram::log_scale(999, 0, 999, 30), 30);
+        assert_eq!(Histogram::log_scale(5000, 0, 999, 30), 30);
+        assert_eq!(Histogram::log_scale(5, 5, 5, 30), 30);
+    }
+
+    #[test]
+    fn test_scale_fractional() {
+        assert_eq!(Histogram::default().scale_fractional(0, 0, 80, 10), (0, 0));
+        assert_eq!(Histogram::default().scale_fractional(1, 0, 80, 10), (0, 1));
+        assert_eq!(Histogram::default().scale_fractional(12, 0, 80, 10), (1, 4));
+        assert_eq!(Histogram::default().scale_fractional(80, 0, 80, 10), (10, 0));
+        assert_eq!(Histogram::default().scale_fractional(90, 0, 80, 10), (10, 0));
+    }
+
     fn dec_v(v :&[&str]) -> Vec<Decimal> {
         v.iter().map(|x| Decimal::from_str_exact(x).unwrap()).collect()
     }
@@ -376,6 +1584,77 @@ mod tests {
                   count)
     }
 
+    #[test]
+    fn test_bucket_width() {
+        let data:Vec<Decimal> = dec_v(&["1.0", "4.0"]);
+        assert_eq!(Buckets::default().width(), None);
+        assert_eq!(Buckets::default()
+                   .set_count(3)
+                   .analyse(&data)
+                   .width(), Some(Decimal::new(1,0)));
+        assert_eq!(Buckets::default()
+                   .set_delta(Decimal::new(5,1))
+                   .analyse(&data)
+                   .width(), Some(Decimal::new(5,1)));
+    }
+
+    #[test]
+    fn test_concurrency() {
+        let timeline: Vec<(Decimal, usize)> = [("1.0", 1), ("1.5", 2), ("3.25", 1), ("4.0", 0), ("6.0", 1)].iter()
+            .map(|(time, level)| (Decimal::from_str_exact(time).unwrap(), *level))
+            .collect();
+        let counts = |buckets: Vec<Bucket>| buckets.into_iter().map(|b| (b.label, b.count)).collect::<Vec<_>>();
+
+        let (buckets, unit) = concurrency_levels(&timeline, None);
+        assert_eq!(unit, Decimal::new(1, 2));
+        assert_eq!(counts(buckets), vec![
+            (String::from("0"), 200),
+            (String::from("1"), 125),
+            (String::from("2"), 175),
+        ]);
+        // or in the units asked for
+        let (buckets, unit) = concurrency_levels(&timeline, Some(Decimal::new(5, 1)));
+        assert_eq!(unit, Decimal::new(5, 1));
+        assert_eq!(counts(buckets), vec![
+            (String::from("0"), 4),
+            (String::from("1"), 3),
+            (String::from("2"), 4),
+        ]);
+        assert_eq!(counts(concurrency_peaks(&timeline, Decimal::new(2, 0))), vec![
+            (String::from("2"), 2),
+            (String::from("4"), 2),
+            (String::from("6"), 0),
+            (String::from("8"), 1),
+        ]);
+        assert!(concurrency_levels(&[], None).0.is_empty());
+        assert!(concurrency_peaks(&timeline, Decimal::ZERO).is_empty());
+    }
+
+    #[test]
+    fn test_stats() {
+        assert_eq!(Stats::new(&[]), None);
+        assert_eq!(Stats::new(&dec_v(&["3"])),
+                   Some(Stats { count: 1, p50: Decimal::new(3, 0), p99: Decimal::new(3, 0) }));
+        let values: Vec<Decimal> = (1..=200).rev().map(Decimal::from).collect();
+        assert_eq!(Stats::new(&values),
+                   Some(Stats { count: 200, p50: Decimal::new(100, 0), p99: Decimal::new(198, 0) }));
+    }
+
+    #[test]
+    fn test_generate_buckets() {
+        let data:Vec<Decimal> = dec_v(&["1.0", "1.5", "2.5", "4.0"]);
+        let buckets = Buckets::default()
+            .set_delta(Decimal::new(1,0))
+            .analyse(&data)
+            .generate_buckets(&data);
+        assert_eq!(buckets, vec![
+            Bucket::range(Decimal::new(1,0), Decimal::new(2,0), 2),
+            Bucket::range(Decimal::new(2,0), Decimal::new(3,0), 1),
+            Bucket::range(Decimal::new(3,0), Decimal::new(4,0), 1),
+        ]);
+        assert_eq!(buckets[0].label, "2");
+    }
+
     #[test]
     fn test_linear_buckets() {
         let data:Vec<Decimal> = dec_v(&["1.0", "4.0"]);
@@ -413,6 +1692,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::identity_op)]
     fn test_scale() {
         // test in a simple range
         let scale = |val: i64| {
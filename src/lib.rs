@@ -4,6 +4,8 @@
 pub mod data;
 pub mod graph;
 pub mod error;
+pub mod output;
+pub mod tree;
 
 pub type Error = error::Error;
 type Result<T> = std::result::Result<T, error::Error>;
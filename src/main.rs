@@ -3,7 +3,13 @@
 
 use clap::{Parser, ArgAction::Append};
 use histo_log::Error;
+use histo_log::graph::{Bucket, Buckets, Histogram, Layout, Renderer, Stats};
+use histo_log::output;
+use histo_log::tree::Frame;
+use histo_log::data::{Key, ScopeDiagnostics, ScopeOptions};
 use regex::Regex;
+use std::collections::BTreeMap;
+use std::io::Write;
 use rust_decimal::Decimal;
 
 /// Quick and dirty analyzer for file generating histograms from
@@ -18,10 +24,81 @@ struct Options {
     #[arg(long, global=true)]
     width: Option<usize>,
 
+    /// Number of rows for the bars of --format=columns
+    #[arg(long, global=true)]
+    height: Option<usize>,
+
+    /// Layout of the graph
+    #[arg(long, value_enum, default_value_t=Format::Bars, global=true)]
+    format: Format,
+
+    /// Output format - "text" draws the graph, the others write the
+    /// bucket data for other tools
+    #[arg(long, value_enum, default_value_t=Output::Text, global=true)]
+    output: Output,
+
+    /// Metric name for --output=openmetrics
+    #[arg(long, value_name="name", value_parser = metric_name, global=true)]
+    metric_name: Option<String>,
+
+    /// Label the ends of --format=spark with the first and last buckets
+    #[arg(long, default_value_t=false, global=true)]
+    spark_labels: bool,
+
     /// Include frequencies (counts)
     #[arg(long, default_value_t=false, global=true)]
     show_counts: bool,
 
+    /// Include each bucket's percentage of the total
+    #[arg(long, default_value_t=false, global=true)]
+    show_percent: bool,
+
+    /// Include cumulative percentage, ie the fraction of entries up
+    /// to and including each bucket
+    #[arg(long, default_value_t=false, global=true)]
+    show_cumulative: bool,
+
+    /// Characters used to draw bars
+    #[arg(long, value_enum, default_value_t=Style::Ascii, global=true)]
+    style: Style,
+
+    /// Which part of labels too long to fit to replace with an ellipsis
+    #[arg(long, value_enum, default_value_t=Truncate::End, global=true)]
+    truncate: Truncate,
+
+    /// How bar length relates to the count.  "log" helps when one
+    /// bucket dominates the others
+    #[arg(long, value_enum, default_value_t=BarScale::Linear, global=true)]
+    bar_scale: BarScale,
+
+    /// When to colour the output.  "auto" colours only when writing
+    /// to a terminal and NO_COLOR is not set
+    #[arg(long, value_enum, value_name="when", default_value_t=ColorChoice::Auto, global=true)]
+    color: ColorChoice,
+
+    /// Colour bars for buckets with values above this yellow
+    #[arg(long, value_name="value", value_parser=parse_decimal, global=true)]
+    warn_above: Option<Decimal>,
+
+    /// Colour bars for buckets with values above this red
+    #[arg(long, value_name="value", value_parser=parse_decimal, global=true)]
+    crit_above: Option<Decimal>,
+
+    /// Title to print above the graph
+    #[arg(long, global=true)]
+    title: Option<String>,
+
+    /// Print a header naming the value column, and a footer
+    /// describing the scale, total samples and bucket width
+    #[arg(long, default_value_t=false, global=true)]
+    legend: bool,
+
+    /// Name of the value column for --legend and chart outputs, eg "interval (s)".
+    ///
+    /// Defaults to a description of the subcommand's values
+    #[arg(long, value_name="name", global=true)]
+    value_label: Option<String>,
+
     /// Input file(s), or if omitted use stdin.
     ///
     /// Use '-' for stdin
@@ -29,6 +106,163 @@ struct Options {
     input: Vec<String>,
 }
 
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Format {
+    /// Horizontal bars, one line per bucket
+    Bars,
+    /// Vertical columns with the labels along the x-axis
+    Columns,
+    /// Single line sparkline, one character per bucket
+    Spark,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Style {
+    /// Whole '#' characters - safe for logs and pipes
+    Ascii,
+    /// Unicode eighth blocks for sub-character resolution
+    Unicode,
+}
+
+impl From<Style> for histo_log::graph::Style {
+    fn from(style: Style) -> Self {
+        match style {
+            Style::Ascii => Self::Ascii,
+            Style::Unicode => Self::Unicode,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum BarScale {
+    /// Bar length is proportional to the value
+    Linear,
+    /// Bar length is proportional to log(value) - for long-tailed data
+    Log,
+}
+
+impl From<BarScale> for histo_log::graph::BarScale {
+    fn from(bar_scale: BarScale) -> Self {
+        match bar_scale {
+            BarScale::Linear => Self::Linear,
+            BarScale::Log => Self::Log,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Truncate {
+    /// Keep the start of the label
+    End,
+    /// Keep the end of the label
+    Start,
+    /// Keep the start and end of the label
+    Middle,
+}
+
+impl From<Truncate> for histo_log::graph::Truncate {
+    fn from(truncate: Truncate) -> Self {
+        match truncate {
+            Truncate::End => Self::End,
+            Truncate::Start => Self::Start,
+            Truncate::Middle => Self::Middle,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Unmatched {
+    /// Summarise them on stderr
+    Report,
+    /// Silently drop them
+    Ignore,
+    /// Summarise them on stderr and fail
+    Error,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Pairing {
+    /// The most recent entry - nested calls and recursion
+    Lifo,
+    /// The oldest entry - queues, where request N pairs with response N
+    Fifo,
+}
+
+impl From<Pairing> for histo_log::data::Pairing {
+    fn from(pairing: Pairing) -> Self {
+        match pairing {
+            Pairing::Lifo => Self::Lifo,
+            Pairing::Fifo => Self::Fifo,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum GroupBy {
+    /// A histogram for each key, on the same buckets.  Data outputs
+    /// have a column or field for each capture name
+    Histograms,
+    /// A row of count, p50 and p99 for each key
+    Table,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Concurrency {
+    /// Time spent at each number of open scopes, in --time-delta units
+    Levels,
+    /// Most scopes open at once in each --time-delta window
+    Peak,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Output {
+    /// Graph as selected by --format
+    Text,
+    /// Comma separated values
+    Csv,
+    /// Tab separated values
+    Tsv,
+    /// Single JSON document
+    Json,
+    /// Newline delimited JSON - one object per bucket
+    Ndjson,
+    /// Standalone SVG chart
+    Svg,
+    /// Standalone HTML page with an embedded chart
+    Html,
+    /// Markdown table with bars
+    Markdown,
+    /// gnuplot script including the data
+    Gnuplot,
+    /// Vega-Lite JSON specification including the data
+    VegaLite,
+    /// OpenMetrics histogram for a textfile collector - time-diff and scoped only
+    Openmetrics,
+    /// Folded stacks for flamegraph tools - tree only
+    Folded,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                // https://no-color.org/ - any non-empty value disables colour
+                let no_color = std::env::var_os("NO_COLOR").map_or(false, |v| !v.is_empty());
+                !no_color && histo_log::graph::Histogram::stdout_is_terminal()
+            }
+        }
+    }
+}
+
 #[derive(clap::Subcommand, Debug)]
 enum Commands {
     /// Simple histogram of frequencies
@@ -40,6 +274,21 @@ enum Commands {
 
     /// Plot distribution of difference between scoped "in and out" matches, matching regex-match fields for in and out matches.  For example open() -> n can match close(n)
     Scoped(Scoped),
+
+    /// Call tree of nested enter and exit matches, with the call count, total and self time of each frame
+    Tree(Tree),
+}
+
+impl Commands {
+    // Default name of the values in the graph's value column
+    fn value_label(&self) -> &'static str {
+        match self {
+            Commands::Simple(_) => "line",
+            Commands::Select(_) => "value",
+            Commands::TimeDiff(_) => "interval",
+            Commands::Scoped(_) | Commands::Tree(_) => "duration",
+        }
+    }
 }
 
 #[derive(clap::Args, Debug)]
@@ -74,7 +323,7 @@ struct Select {
 
 // Common implementation shared via flatten
 #[derive(clap::Args, Debug)]
-struct TimeSelector {
+struct TimeSelect {
     /// Optional regex to extract time values for comparison.
     /// Currently only supports decimal numbers for times.
     ///
@@ -87,6 +336,12 @@ struct TimeSelector {
     ///  - eg "(.*) (?<time>\d+\.\d+)$"
     #[arg(long, value_name="regexp", value_parser = regexp_with_one_match, default_value=r"^(\d+\.\d+)")]
     time_select: Regex,
+}
+
+#[derive(clap::Args, Debug)]
+struct TimeSelector {
+    #[command(flatten)]
+    select: TimeSelect,
 
     /// Divide time series up by buckets of this length
     #[arg(long, value_parser=parse_decimal)]
@@ -107,6 +362,21 @@ struct Scoped {
     #[command(flatten)]
     selections: ScopedSelections,
 
+    /// What to do with exits that have no entry, and entries still open
+    /// at the end of the input
+    #[arg(long, value_enum, default_value_t=Unmatched::Report)]
+    unmatched: Unmatched,
+
+    /// Split the durations by the key captured from the scope regexes -
+    /// one histogram per key, or a table of count and p50/p99 per key
+    #[arg(long, value_enum, value_name="how", num_args=0..=1, require_equals=true, default_missing_value="histograms")]
+    group_by_capture: Option<GroupBy>,
+
+    /// Graph how many scopes were open at once, rather than how long they
+    /// lasted
+    #[arg(long, value_enum, value_name="how", conflicts_with="group_by_capture")]
+    concurrency: Option<Concurrency>,
+
     #[command(flatten)]
     time_selector: TimeSelector,
 }
@@ -115,22 +385,75 @@ struct Scoped {
 #[command(group(clap::ArgGroup::new("one_required")
                 .multiple(false)
                 .required(true)
-                .args(["scope_in", "scope_match"])))]
+                .args(["scope_in", "scope_match", "scopes"])))]
 struct ScopedSelections {
 
-    /// Regex to match for in and out entries in order to determine start/end time.
+    /// Regex to match both in and out entries in order to determine start/end time.
     ///
-    /// TODO - work out what's going on
+    /// Matches toggle: the first match opens a scope, the next match with
+    /// the same captured key (if any) closes it.
     #[arg(short = 'm', long, value_name="regexp", value_parser = regexp, conflicts_with_all(["scope_in", "scope_out"]))]
     scope_match: Option<Regex>,
 
     /// Regex to match in entries in order to determine start time.
+    ///
+    /// Captures key the scope, pairing with the out entry's captures by
+    /// position - or if any are named, eg "(?<id>\d+)", by name alone so
+    /// the out regex may capture them anywhere and unnamed groups are
+    /// ignored.
     #[arg(short = 'i', long, value_name="regexp", value_parser = regexp, requires("scope_out"))]
     scope_in: Option<Regex>,
 
     /// Regex to match out entries in order to determine end time.
     #[arg(short = 'o', long, value_name="regexp", value_parser = regexp, requires("scope_in"))]
     scope_out: Option<Regex>,
+
+    /// File of scopes to time in one pass, one per line as tab separated
+    /// name, in regex and optional out regex (toggling as --scope-match
+    /// if left off).  Each scope gets its own histogram or stats row.
+    #[arg(long, value_name="FILE", conflicts_with="concurrency")]
+    scopes: Option<std::path::PathBuf>,
+
+    /// Which open entry an out entry closes - lifo for nested calls,
+    /// fifo for queued requests and responses
+    #[arg(long, value_enum, default_value_t=Pairing::Lifo)]
+    pairing: Pairing,
+
+    /// Drop in entries still open after this long, reporting them as
+    /// timed out - eg so a lost close doesn't pair with a reused fd
+    #[arg(long, value_name="seconds", value_parser=parse_timeout)]
+    scope_timeout: Option<Decimal>,
+}
+
+/// Call tree of nested enter and exit matches.
+///
+/// Frames are named by the "name" capture of the --enter and --exit
+/// regexes, and an exit closes the most recent open frame of the same
+/// name.  Frames are listed most expensive first beneath their callers.
+#[derive(clap::Args, Debug)]
+struct Tree {
+    /// Regex matching frame entries, with a "name" capture - eg "->(?<name>\w+)"
+    #[arg(short = 'i', long, value_name="regexp", value_parser = regexp_with_name)]
+    enter: Regex,
+
+    /// Regex matching frame exits, with a "name" capture - eg "<-(?<name>\w+)"
+    #[arg(short = 'o', long, value_name="regexp", value_parser = regexp_with_name)]
+    exit: Regex,
+
+    /// What to do with exits that have no entry, and entries never exited
+    #[arg(long, value_enum, default_value_t=Unmatched::Report)]
+    unmatched: Unmatched,
+
+    #[command(flatten)]
+    time_select: TimeSelect,
+}
+
+fn regexp_with_name(s: &str) -> Result<Regex, String> {
+    let re = regexp(s)?;
+    if !re.capture_names().any(|name| name == Some("name")) {
+        return Err(String::from("Need a capture named \"name\" - eg (?<name>\\w+)"));
+    }
+    Ok(re)
 }
 
 fn regexp_with_one_match(s: &str) -> Result<Regex, String> {
@@ -148,6 +471,13 @@ fn regexp(s: &str) -> Result<Regex, String> {
     Ok(re)
 }
 
+fn metric_name(s: &str) -> Result<String, String> {
+    if output::OpenMetrics::valid_name(s) {
+        return Ok(String::from(s));
+    }
+    Err(format!("Invalid metric name {}", s))
+}
+
 fn parse_decimal(s: &str) -> Result<Decimal, String> {
     use std::str::FromStr;
     if let Ok(d) = Decimal::from_str(s) {
@@ -156,29 +486,208 @@ fn parse_decimal(s: &str) -> Result<Decimal, String> {
     Err(format!("Failed to parse {} as decimal", s))
 }
 
-fn print_histo(data: std::collections::BTreeMap<String, i64>, args: &Options) -> Result<(), Error> {
-    if data.is_empty() {
-        return Err(Error::no_data());
+fn parse_timeout(s: &str) -> Result<Decimal, String> {
+    let d = parse_decimal(s)?;
+    if d < Decimal::ZERO {
+        return Err(format!("Timeout {} must not be negative", s))
     }
-    let g = histo_log::graph::Histogram::new_it(&mut data.into_iter())
+    Ok(d)
+}
+
+fn value_label(args: &Options) -> String {
+    let default = match &args.command {
+        Commands::Scoped(Scoped { concurrency: Some(Concurrency::Levels), .. }) => "open scopes",
+        Commands::Scoped(Scoped { concurrency: Some(Concurrency::Peak), .. }) => "time",
+        command => command.value_label(),
+    };
+    args.value_label.clone()
+        .unwrap_or_else(|| String::from(default))
+}
+
+fn configure<'a>(histogram: &'a mut Histogram, args: &Options) -> &'a mut Histogram {
+    let header = args.legend.then(|| value_label(args));
+    histogram
+        .set_title(args.title.clone())
+        .set_header(header)
+        .set_show_footer(args.legend)
         .set_show_counts(args.show_counts)
-        .set_opt_width(args.width).draw()?;
-    println!("{}", g);
-    Ok(())
+        .set_show_percent(args.show_percent)
+        .set_show_cumulative(args.show_cumulative)
+        .set_style(args.style.into())
+        .set_bar_scale(args.bar_scale.into())
+        .set_truncate(args.truncate.into())
+        .set_color(args.color.enabled())
+        .set_warn_above(args.warn_above)
+        .set_crit_above(args.crit_above)
+        .set_opt_height(args.height)
+        .set_spark_labels(args.spark_labels)
+        .set_opt_width(args.width)
 }
 
-fn print_time_histo(data: std::collections::BTreeMap<Decimal, i64>, args: &Options) -> Result<(), Error> {
-    if data.is_empty() {
+fn layout(args: &Options) -> Layout {
+    match args.format {
+        Format::Bars => Layout::Bars,
+        Format::Columns => Layout::Columns,
+        Format::Spark => Layout::Spark,
+    }
+}
+
+fn labels(bucket_width: Option<Decimal>, args: &Options) -> output::Labels {
+    output::Labels {
+        title: args.title.clone(),
+        value_label: Some(value_label(args)),
+        bucket_width,
+    }
+}
+
+fn print_buckets(buckets: Vec<Bucket>, bucket_width: Option<Decimal>, args: &Options) -> Result<(), Error> {
+    if buckets.is_empty() {
         return Err(Error::no_data());
     }
-    let g = histo_log::graph::Histogram::new_it(&mut data.into_iter().map(|(v,c)| (v.to_string(), c) ))
-        .set_show_counts(args.show_counts)
-        .set_opt_width(args.width).draw()?;
-    println!("{}", g);
+    let mut histogram = Histogram::default();
+    let renderer: Box<dyn Renderer> = match args.output {
+        Output::Text => {
+            configure(&mut histogram, args)
+                .set_layout(layout(args))
+                .set_bucket_width(bucket_width);
+            Box::new(histogram)
+        },
+        Output::Markdown => {
+            configure(&mut histogram, args)
+                .set_layout(Layout::Markdown)
+                .set_header(Some(value_label(args)));
+            Box::new(histogram)
+        },
+        Output::Csv => Box::new(output::Csv),
+        Output::Tsv => Box::new(output::Tsv),
+        Output::Json => Box::new(output::Json),
+        Output::Ndjson => Box::new(output::Ndjson),
+        Output::Svg => Box::new(output::Svg::new(labels(bucket_width, args))),
+        Output::Html => Box::new(output::Html { svg: output::Svg::new(labels(bucket_width, args)) }),
+        Output::Openmetrics | Output::Folded => return Err(unsupported(args.output)),
+        Output::Gnuplot => Box::new(output::Gnuplot { labels: labels(bucket_width, args) }),
+        Output::VegaLite => Box::new(output::VegaLite { labels: labels(bucket_width, args) }),
+    };
+    let mut stdout = std::io::stdout().lock();
+    renderer.render_io(&buckets, &mut stdout)?;
+    if args.output == Output::Text {
+        writeln!(stdout)?;
+    }
     Ok(())
 }
 
+fn print_histo(data: std::collections::BTreeMap<String, i64>, args: &Options) -> Result<(), Error> {
+    let buckets = data.into_iter()
+        .map(|(label, count)| Bucket::labelled(label, count))
+        .collect();
+    print_buckets(buckets, None, args)
+}
+
 fn handle_time_buckets(data: Vec<Decimal>, args: &Options) -> Result<(), Error> {
+    handle_keyed_time_buckets(data.into_iter().map(|v| (vec![], v)).collect(), &[], args)
+}
+
+fn group_by_key(data: Vec<(Key, Decimal)>) -> BTreeMap<Key, Vec<Decimal>> {
+    let mut by_key: BTreeMap<Key, Vec<Decimal>> = BTreeMap::new();
+    for (key, v) in data {
+        by_key.entry(key).or_default().push(v);
+    }
+    by_key
+}
+
+// eg "fd=3 op=read"
+fn key_label(key_names: &[String], key: &Key) -> String {
+    key_names.iter().zip(key)
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn unsupported(output: Output) -> Error {
+    use clap::ValueEnum;
+    let name = output.to_possible_value()
+        .map_or_else(|| format!("{:?}", output), |v| String::from(v.get_name()));
+    Error::UnsupportedOutput(name)
+}
+
+// One histogram per key, all on the same buckets so they can be compared
+fn print_grouped_histograms(data: Vec<(Key, Decimal)>, key_names: &[String], buckets: &Buckets, args: &Options) -> Result<(), Error> {
+    if matches!(args.output, Output::Csv | Output::Tsv | Output::Json | Output::Ndjson) {
+        return print_grouped_data(data, key_names, buckets, args);
+    }
+    if !matches!(args.output, Output::Text | Output::Markdown) {
+        return Err(unsupported(args.output));
+    }
+    for (key, values) in group_by_key(data) {
+        println!("{}", key_label(key_names, &key));
+        print_buckets(buckets.generate_buckets(&values), buckets.width(), args)?;
+    }
+    Ok(())
+}
+
+// The per-key buckets of print_grouped_histograms as data
+fn print_grouped_data(data: Vec<(Key, Decimal)>, key_names: &[String], buckets: &Buckets, args: &Options) -> Result<(), Error> {
+    let groups: Vec<output::Group> = group_by_key(data).into_iter()
+        .map(|(key, values)| output::Group {
+            key: key_names.iter().cloned().zip(key).collect(),
+            buckets: buckets.generate_buckets(&values),
+        })
+        .collect();
+    let mut out = String::new();
+    match args.output {
+        Output::Csv => output::Grouped.write_csv(&mut out, &groups)?,
+        Output::Tsv => output::Grouped.write_tsv(&mut out, &groups)?,
+        Output::Json => output::Grouped.write_json(&mut out, &groups)?,
+        Output::Ndjson => output::Grouped.write_ndjson(&mut out, &groups)?,
+        _ => return Err(unsupported(args.output)),
+    }
+    print!("{}", out);
+    Ok(())
+}
+
+fn print_stats_table(data: Vec<(Key, Decimal)>, key_names: &[String], args: &Options) -> Result<(), Error> {
+    let rows: Vec<(String, Stats)> = group_by_key(data).into_iter()
+        .filter_map(|(key, values)| Some((key_label(key_names, &key), Stats::new(&values)?)))
+        .collect();
+    let table = output::StatsTable { label: String::from("key") };
+    let mut out = String::new();
+    match args.output {
+        Output::Text => table.write_text(&mut out, &rows)?,
+        Output::Csv => table.write_csv(&mut out, &rows)?,
+        _ => return Err(unsupported(args.output)),
+    }
+    print!("{}", out);
+    Ok(())
+}
+
+// Histogram of durations per key, labelled with key_names
+fn print_openmetrics(data: Vec<(Key, Decimal)>, key_names: &[String], buckets: &Buckets, args: &Options) -> Result<(), Error> {
+    let series: Vec<output::Series> = group_by_key(data).into_iter()
+        .map(|(key, values)| output::Series::new(key_names.iter().cloned().zip(key).collect(),
+                                                 buckets.generate_buckets(&values), &values))
+        .collect();
+
+    let metrics = output::OpenMetrics {
+        name: args.metric_name.clone()
+            .unwrap_or_else(|| format!("histo_log_{}", args.command.value_label())),
+        help: Some(format!("Distribution of {} from histo-log", value_label(args))),
+    };
+    let mut out = String::new();
+    metrics.write(&mut out, &series)?;
+    print!("{}", out);
+    Ok(())
+}
+
+fn group_by(args: &Options) -> Option<GroupBy> {
+    match &args.command {
+        Commands::Scoped(a) if a.selections.scopes.is_some() =>
+            a.group_by_capture.or(Some(GroupBy::Histograms)),
+        Commands::Scoped(a) => a.group_by_capture,
+        _ => None,
+    }
+}
+
+fn handle_keyed_time_buckets(data: Vec<(Key, Decimal)>, key_names: &[String], args: &Options) -> Result<(), Error> {
     if data.is_empty() {
         return Err(Error::no_data());
     }
@@ -187,13 +696,105 @@ fn handle_time_buckets(data: Vec<Decimal>, args: &Options) -> Result<(), Error>
         Commands::Simple(_) | Commands::Select(_) => { None }
         Commands::TimeDiff(a) => { a.time_selector.time_delta }
         Commands::Scoped(a) => { a.time_selector.time_delta }
+        Commands::Tree(_) => { None }
     };
 
-    let data = histo_log::graph::Buckets::default()
+    let values: Vec<Decimal> = data.iter().map(|(_, v)| *v).collect();
+    let mut buckets = Buckets::default();
+    buckets
         .set_delta_opt(time_delta)
-        .analyse(&data)
-        .generate(&data);
-    print_time_histo(data, args)
+        .analyse(&values);
+
+    if args.output == Output::Openmetrics {
+        return print_openmetrics(data, key_names, &buckets, args);
+    }
+    match group_by(args) {
+        Some(GroupBy::Histograms) => return print_grouped_histograms(data, key_names, &buckets, args),
+        Some(GroupBy::Table) => return print_stats_table(data, key_names, args),
+        None => {},
+    }
+    print_buckets(buckets.generate_buckets(&values), buckets.width(), args)
+}
+
+fn report_unmatched(diagnostics: &ScopeDiagnostics, key_names: &[String], policy: Unmatched) -> Result<(), Error> {
+    report_unmatched_scopes(&[(None, diagnostics, key_names.to_vec())], policy)
+}
+
+// Report each scope's unpaired entries and exits, under the scope's name
+// if given
+fn report_unmatched_scopes(scopes: &[(Option<&str>, &ScopeDiagnostics, Vec<String>)], policy: Unmatched) -> Result<(), Error> {
+    if policy == Unmatched::Ignore {
+        return Ok(());
+    }
+    let mut report = String::new();
+    let mut count = 0;
+    for (name, diagnostics, key_names) in scopes.iter().filter(|(_, diagnostics, _)| !diagnostics.is_empty()) {
+        if let Some(name) = name {
+            report.push_str(&format!("{}:\n", name));
+        }
+        diagnostics.write_report(&mut report, key_names)?;
+        count += diagnostics.len();
+    }
+    eprint!("{}", report);
+    if policy == Unmatched::Error && count > 0 {
+        return Err(Error::UnmatchedScopes(count));
+    }
+    Ok(())
+}
+
+fn scopes_load(input: Vec<String>, file: &std::path::Path, a: &Scoped) -> Result<Vec<(Key, Decimal)>, Error> {
+    let scopes = histo_log::data::parse_scopes(&std::fs::read_to_string(file)?)?;
+    let data = histo_log::data::scopes_load(
+        input, &a.time_selector.select.time_select, &scopes,
+        ScopeOptions::default()
+            .set_pairing(a.selections.pairing.into())
+            .set_timeout(a.selections.scope_timeout))?;
+    let diagnostics: Vec<_> = scopes.iter().zip(&data)
+        .map(|(scope, data)| (Some(scope.name.as_str()), &data.diagnostics, histo_log::data::key_names(&scope.scoped_in)))
+        .collect();
+    report_unmatched_scopes(&diagnostics, a.unmatched)?;
+    Ok(scopes.iter().zip(data)
+       .flat_map(|(scope, data)| data.durations.into_iter().map(move |(_, v)| (vec![scope.name.clone()], v)))
+       .collect())
+}
+
+fn print_concurrency(timeline: &[(Decimal, usize)], how: Concurrency, time_delta: Option<Decimal>, args: &Options) -> Result<(), Error> {
+    match how {
+        Concurrency::Levels => {
+            let (buckets, unit) = histo_log::graph::concurrency_levels(timeline, time_delta);
+            print_buckets(buckets, None, args)?;
+            if args.output == Output::Text {
+                println!("time at each level in units of {}", unit);
+            }
+            Ok(())
+        }
+        Concurrency::Peak => {
+            let times: Vec<Decimal> = timeline.iter().map(|(time, _)| *time).collect();
+            let width = Buckets::default()
+                .set_delta_opt(time_delta)
+                .analyse(&times)
+                .width();
+            let buckets = width
+                .map(|width| histo_log::graph::concurrency_peaks(timeline, width))
+                .unwrap_or_default();
+            print_buckets(buckets, width, args)
+        }
+    }
+}
+
+fn print_tree(tree: &Frame, args: &Options) -> Result<(), Error> {
+    if tree.children.is_empty() {
+        return Err(Error::no_data());
+    }
+    let mut out = String::new();
+    match args.output {
+        Output::Text => tree.write_report(&mut out)?,
+        Output::Csv => tree.write_csv(&mut out)?,
+        Output::Folded => tree.write_folded(&mut out)?,
+        _ => return Err(unsupported(args.output)),
+    }
+    print!("{}", out);
+    Ok(())
 }
 
 // parse options, and split off the input which we will consume just once
@@ -204,36 +805,64 @@ fn parse_options() -> (Options, Vec<String>) {
     (args, input)
 }
 
+fn print_error(e: &Error, args: &Options) {
+    match args.output {
+        Output::Json | Output::Ndjson => println!("{}", output::json_error(e)),
+        _ => println!("{}\n", e),
+    }
+}
+
 // hmm,... the errors returned through main don't print via display - so print it manually
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    run().map_err(|e| { println!("{}\n", e); e} )?;
+    let (args, input) = parse_options();
+    run(&args, input).map_err(|e| { print_error(&e, &args); e} )?;
     Ok(())
 }
 
-fn run() -> Result<(), Error> {
-    let (args, input) = parse_options();
+fn run(args: &Options, input: Vec<String>) -> Result<(), Error> {
 
     match &args.command {
         Commands::Simple(a) => {
             let data = histo_log::data::simple_load_w_filter(input, &a.optional_match.match_);
-            print_histo(data, &args)?;
+            print_histo(data, args)?;
         },
         Commands::Select(a) => {
             let data = histo_log::data::select_load(input, &a.selector);
-            print_histo(data, &args)?;
+            print_histo(data, args)?;
         },
         Commands::TimeDiff(a) => {
-            let data = histo_log::data::time_diff_load(input, &a.time_selector.time_select, &a.optional_match.match_);
-            handle_time_buckets(data, &args)?;
+            let data = histo_log::data::time_diff_load(input, &a.time_selector.select.time_select, &a.optional_match.match_);
+            handle_time_buckets(data, args)?;
+        }
+        Commands::Scoped(a) if a.selections.scopes.is_some() => {
+            let file = a.selections.scopes.as_ref().expect("Must have --scopes");
+            let data = scopes_load(input, file, a)?;
+            handle_keyed_time_buckets(data, &[String::from("scope")], args)?;
         }
         Commands::Scoped(a) => {
             let in_match = a.selections.scope_in.as_ref().or(a.selections.scope_match.as_ref());
             let out_match = a.selections.scope_out.as_ref().or(a.selections.scope_match.as_ref());
-            let data = histo_log::data::scoped_match_time_load(
-                input, &a.time_selector.time_select,
-                in_match.expect("Must have either --scope-match or --scope-in"),
-                out_match.expect("Must have either --scope-match or --scope-out"))?;
-            handle_time_buckets(data, &args)?;
+            let in_match = in_match.expect("Must have either --scope-match or --scope-in");
+            let data = histo_log::data::scoped_match_time_load_keyed(
+                input, &a.time_selector.select.time_select,
+                in_match,
+                out_match.expect("Must have either --scope-match or --scope-out"),
+                ScopeOptions::default()
+                    .set_pairing(a.selections.pairing.into())
+                    .set_timeout(a.selections.scope_timeout)
+                    .set_concurrency(a.concurrency.is_some()))?;
+            let key_names = histo_log::data::key_names(in_match);
+            report_unmatched(&data.diagnostics, &key_names, a.unmatched)?;
+            match a.concurrency {
+                Some(how) => print_concurrency(&data.concurrency, how, a.time_selector.time_delta, args)?,
+                None => handle_keyed_time_buckets(data.durations, &key_names, args)?,
+            }
+        }
+        Commands::Tree(a) => {
+            let (tree, diagnostics) = histo_log::tree::tree_load(
+                input, &a.time_select.time_select, &a.enter, &a.exit);
+            report_unmatched(&diagnostics, &[String::from("name")], a.unmatched)?;
+            print_tree(&tree, args)?;
         }
     }
 
@@ -0,0 +1,867 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// (C) Copyright 2023-2024 Greg Whiteley
+
+// Machine readable output of bucketed data
+
+use std::fmt::Write;
+use rust_decimal::prelude::*;
+
+use unicode_width::UnicodeWidthStr;
+use super::graph::{pad_right, Bucket, Renderer, Stats};
+use super::{Result, Error};
+
+const PERCENT_DP: u32 = 4;
+
+fn total(buckets: &[Bucket]) -> i64 {
+    buckets.iter().map(|b| b.count).sum()
+}
+
+fn percent(count: i64, total: i64) -> Decimal {
+    Bucket::percent_of(count, total)
+        .round_dp(PERCENT_DP)
+        .normalize()
+}
+
+fn opt_to_string(d: Option<Decimal>) -> String {
+    d.map(|d| d.to_string()).unwrap_or_default()
+}
+
+// RFC 4180 - quote fields containing separators, quotes or line breaks
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        String::from(s)
+    }
+}
+
+// TSV has no quoting, so escape the characters that would break the row
+fn tsv_field(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_number(d: Option<Decimal>) -> String {
+    d.map(|d| d.to_string()).unwrap_or_else(|| String::from("null"))
+}
+
+fn json_bucket(b: &Bucket, total: i64) -> String {
+    format!("{{\"label\":{},\"lower\":{},\"upper\":{},\"count\":{},\"percent\":{}}}",
+            json_string(&b.label), json_number(b.lower), json_number(b.upper),
+            b.count, percent(b.count, total))
+}
+
+const DELIMITED_HEADINGS: [&str; 5] = ["label", "lower", "upper", "count", "percent"];
+
+// Each row starts with the key fields, if any, then the bucket
+fn write_delimited_rows<F>(w: &mut dyn Write, key: &[String], buckets: &[Bucket], sep: char, field: F) -> Result<()>
+where
+    F: Fn(&str) -> String
+{
+    let total = total(buckets);
+    for b in buckets {
+        for value in key {
+            write!(w, "{}{}", field(value), sep)?;
+        }
+        writeln!(w, "{}{sep}{}{sep}{}{sep}{}{sep}{}",
+                 field(&b.label), opt_to_string(b.lower), opt_to_string(b.upper),
+                 b.count, percent(b.count, total), sep = sep)?;
+    }
+    Ok(())
+}
+
+fn write_delimited<F>(w: &mut dyn Write, buckets: &[Bucket], sep: char, field: F) -> Result<()>
+where
+    F: Fn(&str) -> String
+{
+    writeln!(w, "{}", DELIMITED_HEADINGS.join(&sep.to_string()))?;
+    write_delimited_rows(w, &[], buckets, sep, field)
+}
+
+/// Comma separated values with a header row
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Csv;
+
+impl Renderer for Csv {
+    fn render(&self, buckets: &[Bucket], w: &mut dyn Write) -> Result<()> {
+        write_delimited(w, buckets, ',', csv_field)
+    }
+}
+
+/// Tab separated values with a header row
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Tsv;
+
+impl Renderer for Tsv {
+    fn render(&self, buckets: &[Bucket], w: &mut dyn Write) -> Result<()> {
+        write_delimited(w, buckets, '\t', tsv_field)
+    }
+}
+
+/// A single JSON document holding all buckets
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Json;
+
+impl Renderer for Json {
+    fn render(&self, buckets: &[Bucket], w: &mut dyn Write) -> Result<()> {
+        let total = total(buckets);
+        let rows: Vec<String> = buckets.iter().map(|b| json_bucket(b, total)).collect();
+        writeln!(w, "{{\"total\":{},\"buckets\":[{}]}}", total, rows.join(","))?;
+        Ok(())
+    }
+}
+
+/// One JSON object per bucket per line
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Ndjson;
+
+impl Renderer for Ndjson {
+    fn render(&self, buckets: &[Bucket], w: &mut dyn Write) -> Result<()> {
+        let total = total(buckets);
+        for b in buckets {
+            writeln!(w, "{}", json_bucket(b, total))?;
+        }
+        Ok(())
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Descriptive text shared by the chart formats
+#[derive(Debug, Default, Clone)]
+pub struct Labels {
+    pub title: Option<String>,
+    /// Name of the x-axis, eg "interval (s)"
+    pub value_label: Option<String>,
+    pub bucket_width: Option<Decimal>,
+}
+
+impl Labels {
+    /// One line description of the data, eg total samples and the busiest bucket
+    pub fn summary(&self, buckets: &[Bucket]) -> String {
+        let mut parts = vec![
+            format!("{} samples", total(buckets)),
+            format!("{} buckets", buckets.len()),
+        ];
+        if let Some(width) = self.bucket_width {
+            parts.push(format!("bucket width {}", width));
+        }
+        // first of equal peaks
+        if let Some(peak) = buckets.iter().rev().max_by_key(|b| b.count) {
+            parts.push(format!("peak {} at {}", peak.count, peak.label));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Standalone SVG bar chart, with native (script-free) hover tooltips
+#[derive(Debug)]
+pub struct Svg {
+    labels: Labels,
+    width: usize,
+    height: usize,
+}
+
+impl Svg {
+    const MARGIN_LEFT: f64 = 60.0;
+    const MARGIN_RIGHT: f64 = 20.0;
+    const MARGIN_TOP: f64 = 40.0;
+    const MARGIN_BOTTOM: f64 = 90.0;
+    const Y_TICKS: i64 = 5;
+
+    pub fn new(labels: Labels) -> Self {
+        Svg { labels, width: 800, height: 400 }
+    }
+
+    /// Image size in pixels
+    pub fn set_size(&mut self, width: usize, height: usize) -> &mut Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+}
+
+impl Renderer for Svg {
+    fn render(&self, buckets: &[Bucket], w: &mut dyn Write) -> Result<()> {
+        let (width, height) = (self.width as f64, self.height as f64);
+        let plot_width = (width - Self::MARGIN_LEFT - Self::MARGIN_RIGHT).max(1.0);
+        let plot_height = (height - Self::MARGIN_TOP - Self::MARGIN_BOTTOM).max(1.0);
+        let bottom = Self::MARGIN_TOP + plot_height;
+
+        // round the y-axis up to a whole number of ticks
+        let max = buckets.iter().map(|b| b.count).max().unwrap_or(0).max(1);
+        let ticks = Self::Y_TICKS.min(max);
+        let step = (max + ticks - 1) / ticks;
+        let y_max = (step * ticks) as f64;
+        let y = |v: i64| bottom - (v as f64 * plot_height / y_max);
+
+        writeln!(w, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}" font-family="sans-serif" font-size="12">"#,
+                 self.width, self.height, self.width, self.height)?;
+        writeln!(w, r#"<rect width="100%" height="100%" fill="white"/>"#)?;
+        if let Some(title) = &self.labels.title {
+            writeln!(w, r#"<text x="{:.1}" y="20" text-anchor="middle" font-size="16">{}</text>"#,
+                     width / 2.0, xml_escape(title))?;
+        }
+
+        // y-axis with ticks and grid lines
+        for tick in 0..=ticks {
+            let v = tick * step;
+            writeln!(w, r##"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}" stroke="#ddd"/>"##,
+                     Self::MARGIN_LEFT, y(v), Self::MARGIN_LEFT + plot_width, y(v))?;
+            writeln!(w, r#"<text x="{:.1}" y="{:.1}" text-anchor="end" dominant-baseline="middle">{}</text>"#,
+                     Self::MARGIN_LEFT - 6.0, y(v), v)?;
+        }
+        writeln!(w, r#"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}" stroke="black"/>"#,
+                 Self::MARGIN_LEFT, Self::MARGIN_TOP, Self::MARGIN_LEFT, bottom)?;
+        writeln!(w, r#"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}" stroke="black"/>"#,
+                 Self::MARGIN_LEFT, bottom, Self::MARGIN_LEFT + plot_width, bottom)?;
+
+        // bars, each with a tooltip
+        let total = total(buckets);
+        let slot = plot_width / buckets.len().max(1) as f64;
+        let rotate = buckets.len() > 12;
+        for (ix, b) in buckets.iter().enumerate() {
+            let x = Self::MARGIN_LEFT + slot * ix as f64;
+            let range = match (b.lower, b.upper) {
+                (Some(lower), Some(upper)) => format!("{} - {}", lower, upper),
+                _ => b.label.clone(),
+            };
+            writeln!(w, r##"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="#4682b4"><title>{}: {} ({}%)</title></rect>"##,
+                     x + slot * 0.1, y(b.count), slot * 0.8, bottom - y(b.count),
+                     xml_escape(&range), b.count, percent(b.count, total))?;
+
+            let label_x = x + slot / 2.0;
+            let label_y = bottom + 14.0;
+            if rotate {
+                writeln!(w, r#"<text x="{:.1}" y="{:.1}" text-anchor="end" transform="rotate(-45 {:.1} {:.1})">{}</text>"#,
+                         label_x, label_y, label_x, label_y, xml_escape(&b.label))?;
+            } else {
+                writeln!(w, r#"<text x="{:.1}" y="{:.1}" text-anchor="middle">{}</text>"#,
+                         label_x, label_y, xml_escape(&b.label))?;
+            }
+        }
+
+        if let Some(label) = &self.labels.value_label {
+            writeln!(w, r#"<text x="{:.1}" y="{:.1}" text-anchor="middle">{}</text>"#,
+                     Self::MARGIN_LEFT + plot_width / 2.0, height - 24.0, xml_escape(label))?;
+        }
+        writeln!(w, r##"<text x="{:.1}" y="{:.1}" text-anchor="middle" fill="#555">{}</text>"##,
+                 Self::MARGIN_LEFT + plot_width / 2.0, height - 6.0, xml_escape(&self.labels.summary(buckets)))?;
+        writeln!(w, "</svg>")?;
+        Ok(())
+    }
+}
+
+/// Standalone HTML page wrapping an Svg chart - no scripts or external resources
+#[derive(Debug)]
+pub struct Html {
+    pub svg: Svg,
+}
+
+impl Renderer for Html {
+    fn render(&self, buckets: &[Bucket], w: &mut dyn Write) -> Result<()> {
+        let title = self.svg.labels.title.as_deref().unwrap_or("histo-log");
+        writeln!(w, "<!DOCTYPE html>")?;
+        writeln!(w, "<html>")?;
+        writeln!(w, "<head>")?;
+        writeln!(w, r#"<meta charset="utf-8">"#)?;
+        writeln!(w, "<title>{}</title>", xml_escape(title))?;
+        writeln!(w, "<style>body {{ font-family: sans-serif; }} rect:hover {{ opacity: 0.7; }}</style>")?;
+        writeln!(w, "</head>")?;
+        writeln!(w, "<body>")?;
+        writeln!(w, "<h1>{}</h1>", xml_escape(title))?;
+        self.svg.render(buckets, w)?;
+        writeln!(w, "<p>{}</p>", xml_escape(&self.svg.labels.summary(buckets)))?;
+        writeln!(w, "</body>")?;
+        writeln!(w, "</html>")?;
+        Ok(())
+    }
+}
+
+/// gnuplot script with the data embedded as a datablock - run with `gnuplot -p`
+#[derive(Debug, Default)]
+pub struct Gnuplot {
+    pub labels: Labels,
+}
+
+impl Gnuplot {
+    // double quoted, so backslash escapes apply
+    fn string(s: &str) -> String {
+        format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', " "))
+    }
+}
+
+impl Renderer for Gnuplot {
+    fn render(&self, buckets: &[Bucket], w: &mut dyn Write) -> Result<()> {
+        writeln!(w, "# {}", self.labels.summary(buckets))?;
+        writeln!(w, "$data << EOD")?;
+        writeln!(w, "# label lower upper count")?;
+        for b in buckets {
+            writeln!(w, "{} {} {} {}", Self::string(&b.label),
+                     b.lower.map_or(String::from("NaN"), |d| d.to_string()),
+                     b.upper.map_or(String::from("NaN"), |d| d.to_string()),
+                     b.count)?;
+        }
+        writeln!(w, "EOD")?;
+        if let Some(title) = &self.labels.title {
+            writeln!(w, "set title {}", Self::string(title))?;
+        }
+        if let Some(label) = &self.labels.value_label {
+            writeln!(w, "set xlabel {}", Self::string(label))?;
+        }
+        writeln!(w, "set ylabel \"count\"")?;
+        writeln!(w, "set style fill solid 0.8")?;
+        writeln!(w, "set boxwidth 0.8")?;
+        writeln!(w, "set yrange [0:*]")?;
+        writeln!(w, "set xtics rotate by -45")?;
+        writeln!(w, "plot $data using 0:4:xtic(1) with boxes notitle")?;
+        Ok(())
+    }
+}
+
+/// Vega-Lite bar chart specification with the data embedded
+#[derive(Debug, Default)]
+pub struct VegaLite {
+    pub labels: Labels,
+}
+
+impl VegaLite {
+    const SCHEMA: &'static str = "https://vega.github.io/schema/vega-lite/v5.json";
+}
+
+impl Renderer for VegaLite {
+    fn render(&self, buckets: &[Bucket], w: &mut dyn Write) -> Result<()> {
+        let total = total(buckets);
+        let values: Vec<String> = buckets.iter().map(|b| json_bucket(b, total)).collect();
+        let x_title = self.labels.value_label.as_deref().unwrap_or("label");
+
+        write!(w, "{{\"$schema\":{}", json_string(Self::SCHEMA))?;
+        if let Some(title) = &self.labels.title {
+            write!(w, ",\"title\":{}", json_string(title))?;
+        }
+        write!(w, ",\"description\":{}", json_string(&self.labels.summary(buckets)))?;
+        write!(w, ",\"data\":{{\"values\":[{}]}}", values.join(","))?;
+        write!(w, ",\"mark\":\"bar\"")?;
+        // sort:null keeps the bucket order the same as the terminal view
+        write!(w, ",\"encoding\":{{\"x\":{{\"field\":\"label\",\"type\":\"ordinal\",\"sort\":null,\"title\":{}}}", json_string(x_title))?;
+        write!(w, ",\"y\":{{\"field\":\"count\",\"type\":\"quantitative\"}}")?;
+        write!(w, ",\"tooltip\":[{{\"field\":\"label\"}},{{\"field\":\"count\"}},{{\"field\":\"percent\"}}]}}")?;
+        writeln!(w, "}}")?;
+        Ok(())
+    }
+}
+
+/// Buckets for one group of values, eg a scope, all groups sharing the
+/// same bucket boundaries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    /// (name, value) of each part of the group's key, eg ("scope", "reset")
+    pub key: Vec<(String, String)>,
+    pub buckets: Vec<Bucket>,
+}
+
+impl Group {
+    fn key_values(&self) -> Vec<String> {
+        self.key.iter().map(|(_, value)| value.clone()).collect()
+    }
+
+    fn json_key(&self) -> String {
+        let mut out = String::new();
+        for (name, value) in &self.key {
+            out.push_str(&json_string(name));
+            out.push(':');
+            out.push_str(&json_string(value));
+            out.push(',');
+        }
+        out
+    }
+}
+
+/// Buckets for several groups - the key names become leading columns of
+/// the delimited formats and fields of the JSON ones
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Grouped;
+
+impl Grouped {
+    fn write_delimited<F>(w: &mut dyn Write, groups: &[Group], sep: char, field: F) -> Result<()>
+    where
+        F: Fn(&str) -> String + Copy
+    {
+        let names = groups.first().map_or(&[][..], |g| &g.key[..]);
+        let headings: Vec<String> = names.iter().map(|(name, _)| field(name))
+            .chain(DELIMITED_HEADINGS.iter().map(|h| String::from(*h)))
+            .collect();
+        writeln!(w, "{}", headings.join(&sep.to_string()))?;
+        for group in groups {
+            write_delimited_rows(w, &group.key_values(), &group.buckets, sep, field)?;
+        }
+        Ok(())
+    }
+
+    /// Comma separated values with a header row
+    pub fn write_csv(&self, w: &mut dyn Write, groups: &[Group]) -> Result<()> {
+        Self::write_delimited(w, groups, ',', csv_field)
+    }
+
+    /// Tab separated values with a header row
+    pub fn write_tsv(&self, w: &mut dyn Write, groups: &[Group]) -> Result<()> {
+        Self::write_delimited(w, groups, '\t', tsv_field)
+    }
+
+    /// A single JSON document holding one object per group
+    pub fn write_json(&self, w: &mut dyn Write, groups: &[Group]) -> Result<()> {
+        let objects: Vec<String> = groups.iter()
+            .map(|g| {
+                let total = total(&g.buckets);
+                let rows: Vec<String> = g.buckets.iter().map(|b| json_bucket(b, total)).collect();
+                format!("{{{}\"total\":{},\"buckets\":[{}]}}", g.json_key(), total, rows.join(","))
+            })
+            .collect();
+        writeln!(w, "{{\"groups\":[{}]}}", objects.join(","))?;
+        Ok(())
+    }
+
+    /// One JSON object per bucket per line, led by the group's key
+    pub fn write_ndjson(&self, w: &mut dyn Write, groups: &[Group]) -> Result<()> {
+        for g in groups {
+            let total = total(&g.buckets);
+            for b in &g.buckets {
+                // splice the key in after json_bucket's opening brace
+                writeln!(w, "{{{}{}", g.json_key(), &json_bucket(b, total)[1..])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Table of Stats, one row per labelled group of values
+#[derive(Debug, Default)]
+pub struct StatsTable {
+    /// Heading of the label column
+    pub label: String,
+}
+
+impl StatsTable {
+    const HEADINGS: [&'static str; 3] = ["count", "p50", "p99"];
+
+    fn cells(stats: &Stats) -> [String; 3] {
+        [stats.count.to_string(), stats.p50.to_string(), stats.p99.to_string()]
+    }
+
+    /// Aligned columns for the terminal
+    pub fn write_text(&self, w: &mut dyn Write, rows: &[(String, Stats)]) -> Result<()> {
+        let label_width = rows.iter()
+            .map(|(label, _)| label.width())
+            .chain(std::iter::once(self.label.width()))
+            .max()
+            .unwrap_or(0);
+        let cells: Vec<[String; 3]> = rows.iter().map(|(_, stats)| Self::cells(stats)).collect();
+        let mut widths = Self::HEADINGS.map(str::len);
+        for row in &cells {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+
+        write!(w, "{}", pad_right(&self.label, label_width))?;
+        for (heading, width) in Self::HEADINGS.iter().zip(widths) {
+            write!(w, "  {:>width$}", heading, width = width)?;
+        }
+        writeln!(w)?;
+        for ((label, _), row) in rows.iter().zip(&cells) {
+            write!(w, "{}", pad_right(label, label_width))?;
+            for (cell, width) in row.iter().zip(widths) {
+                write!(w, "  {:>width$}", cell, width = width)?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    /// Comma separated values with a header row
+    pub fn write_csv(&self, w: &mut dyn Write, rows: &[(String, Stats)]) -> Result<()> {
+        writeln!(w, "{},{}", csv_field(&self.label), Self::HEADINGS.join(","))?;
+        for (label, stats) in rows {
+            writeln!(w, "{},{}", csv_field(label), Self::cells(stats).join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// One labelled histogram for OpenMetrics exposition
+#[derive(Debug)]
+pub struct Series {
+    pub labels: Vec<(String, String)>,
+    pub buckets: Vec<Bucket>,
+    /// Sum of the values counted by the buckets
+    pub sum: Decimal,
+}
+
+impl Series {
+    /// Series of values over buckets with value ranges.  Each bucket
+    /// counts the values above the previous bucket's upper bound up to
+    /// and including its own, as "le" means - where Buckets puts a value
+    /// on a bound into the next bucket.
+    pub fn new(labels: Vec<(String, String)>, buckets: Vec<Bucket>, values: &[Decimal]) -> Self {
+        let mut below = 0;
+        let buckets = buckets.into_iter()
+            .map(|mut b| {
+                if let Some(upper) = b.upper {
+                    let le = values.iter().filter(|v| **v <= upper).count() as i64;
+                    b.count = le - below;
+                    below = le;
+                }
+                b
+            })
+            .collect();
+        Series { labels, buckets, sum: values.iter().sum() }
+    }
+}
+
+/// OpenMetrics (Prometheus) text exposition of histograms with cumulative buckets
+#[derive(Debug)]
+pub struct OpenMetrics {
+    pub name: String,
+    pub help: Option<String>,
+}
+
+impl OpenMetrics {
+    /// Metric names must match [a-zA-Z_:][a-zA-Z0-9_:]*
+    pub fn valid_name(name: &str) -> bool {
+        let mut chars = name.chars();
+        chars.next().map_or(false, |c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+    }
+
+    /// Label names must match [a-zA-Z_][a-zA-Z0-9_]*, and histograms
+    /// reserve "le" for the bucket bound
+    pub fn valid_label_name(name: &str) -> bool {
+        let mut chars = name.chars();
+        name != "le"
+            && chars.next().map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    fn label_value(s: &str) -> String {
+        s.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    }
+
+    fn labels(labels: &[(String, String)], le: Option<&str>) -> String {
+        let mut all: Vec<String> = labels.iter()
+            .map(|(k, v)| format!("{}=\"{}\"", k, Self::label_value(v)))
+            .collect();
+        if let Some(le) = le {
+            all.push(format!("le=\"{}\"", le));
+        }
+        if all.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", all.join(","))
+        }
+    }
+
+    pub fn write(&self, w: &mut dyn Write, series: &[Series]) -> Result<()> {
+        if let Some((label, _)) = series.iter()
+            .flat_map(|s| &s.labels)
+            .find(|(label, _)| !Self::valid_label_name(label)) {
+            return Err(Error::InvalidLabelName(label.clone()));
+        }
+        let name = &self.name;
+        if let Some(help) = &self.help {
+            writeln!(w, "# HELP {} {}", name, help.replace('\\', "\\\\").replace('\n', "\\n"))?;
+        }
+        writeln!(w, "# TYPE {} histogram", name)?;
+        for s in series {
+            let mut cumulative = 0;
+            for b in &s.buckets {
+                cumulative += b.count;
+                let le = b.upper.map_or_else(|| b.label.clone(), |u| u.to_string());
+                writeln!(w, "{}_bucket{} {}", name, Self::labels(&s.labels, Some(&le)), cumulative)?;
+            }
+            writeln!(w, "{}_bucket{} {}", name, Self::labels(&s.labels, Some("+Inf")), cumulative)?;
+            writeln!(w, "{}_sum{} {}", name, Self::labels(&s.labels, None), s.sum)?;
+            writeln!(w, "{}_count{} {}", name, Self::labels(&s.labels, None), cumulative)?;
+        }
+        writeln!(w, "# EOF")?;
+        Ok(())
+    }
+}
+
+/// Report an error as a JSON object
+pub fn json_error(e: &super::Error) -> String {
+    format!("{{\"error\":{}}}", json_string(&e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(s: &str) -> Decimal {
+        Decimal::from_str_exact(s).unwrap()
+    }
+
+    fn time_buckets() -> Vec<Bucket> {
+        vec![
+            Bucket::range(d("0"), d("0.5"), 1),
+            Bucket::range(d("0.5"), d("1.0"), 2),
+        ]
+    }
+
+    fn simple_buckets() -> Vec<Bucket> {
+        vec![
+            Bucket::labelled(String::from("a,\"b\""), 1),
+            Bucket::labelled(String::from("c\td"), 3),
+        ]
+    }
+
+    #[test]
+    fn test_csv() {
+        assert_eq!(Csv.render_to_string(&time_buckets()).unwrap(), "label,lower,upper,count,percent
+0.5,0,0.5,1,33.3333
+1.0,0.5,1.0,2,66.6667
+");
+        assert_eq!(Csv.render_to_string(&simple_buckets()).unwrap(), "label,lower,upper,count,percent
+\"a,\"\"b\"\"\",,,1,25
+c\td,,,3,75
+");
+    }
+
+    #[test]
+    fn test_tsv() {
+        assert_eq!(Tsv.render_to_string(&simple_buckets()).unwrap(), "label\tlower\tupper\tcount\tpercent
+a,\"b\"\t\t\t1\t25
+c\\td\t\t\t3\t75
+");
+    }
+
+    fn labels() -> Labels {
+        Labels {
+            title: Some(String::from("<title>")),
+            value_label: Some(String::from("interval (s)")),
+            bucket_width: Some(d("0.5")),
+        }
+    }
+
+    #[test]
+    fn test_gnuplot() {
+        let s = Gnuplot { labels: labels() }.render_to_string(&time_buckets()).unwrap();
+        assert_eq!(s, r#"# 3 samples, 2 buckets, bucket width 0.5, peak 2 at 1.0
+$data << EOD
+# label lower upper count
+"0.5" 0 0.5 1
+"1.0" 0.5 1.0 2
+EOD
+set title "<title>"
+set xlabel "interval (s)"
+set ylabel "count"
+set style fill solid 0.8
+set boxwidth 0.8
+set yrange [0:*]
+set xtics rotate by -45
+plot $data using 0:4:xtic(1) with boxes notitle
+"#);
+
+        let s = Gnuplot::default().render_to_string(&simple_buckets()).unwrap();
+        assert!(s.contains("\n\"a,\\\"b\\\"\" NaN NaN 1\n"));
+        assert_eq!(Gnuplot::string(r"c:\"), r#""c:\\""#);
+    }
+
+    #[test]
+    fn test_vega_lite() {
+        let s = VegaLite { labels: labels() }.render_to_string(&time_buckets()).unwrap();
+        assert_eq!(s, concat!(
+            r#"{"$schema":"https://vega.github.io/schema/vega-lite/v5.json","title":"<title>","#,
+            r#""description":"3 samples, 2 buckets, bucket width 0.5, peak 2 at 1.0","#,
+            r#""data":{"values":[{"label":"0.5","lower":0,"upper":0.5,"count":1,"percent":33.3333},"#,
+            r#"{"label":"1.0","lower":0.5,"upper":1.0,"count":2,"percent":66.6667}]},"#,
+            r#""mark":"bar","encoding":{"x":{"field":"label","type":"ordinal","sort":null,"title":"interval (s)"},"#,
+            r#""y":{"field":"count","type":"quantitative"},"#,
+            r#""tooltip":[{"field":"label"},{"field":"count"},{"field":"percent"}]}}"#, "\n"));
+    }
+
+    #[test]
+    fn test_svg() {
+        let mut svg = Svg::new(labels());
+        svg.set_size(200, 200);
+
+        let s = svg.render_to_string(&time_buckets()).unwrap();
+        println!("{}", s);
+        assert!(s.starts_with("<svg "));
+        assert!(s.ends_with("</svg>\n"));
+        assert!(s.contains(">&lt;title&gt;</text>"));
+        assert!(s.contains("<title>0.5 - 1.0: 2 (66.6667%)</title>"));
+        assert!(s.contains(">interval (s)</text>"));
+        assert!(s.contains(">3 samples, 2 buckets, bucket width 0.5, peak 2 at 1.0</text>"));
+        // tallest bar fills the plot area: 200 - 40 (top) - 90 (bottom)
+        assert!(s.contains(r#"y="40.0" width="48.0" height="70.0""#));
+
+        let s = Html { svg }.render_to_string(&time_buckets()).unwrap();
+        assert!(s.starts_with("<!DOCTYPE html>"));
+        assert!(s.contains("<h1>&lt;title&gt;</h1>"));
+        assert!(!s.contains("<script"));
+    }
+
+    #[test]
+    fn test_openmetrics() {
+        let series = vec![
+            Series {
+                labels: vec![(String::from("key"), String::from("re\"set"))],
+                buckets: time_buckets(),
+                sum: d("2.2"),
+            },
+            Series {
+                labels: vec![(String::from("key"), String::from("recurse"))],
+                buckets: vec![Bucket::range(d("0"), d("0.5"), 0), Bucket::range(d("0.5"), d("1.0"), 1)],
+                sum: d("0.75"),
+            },
+        ];
+        let mut s = String::new();
+        OpenMetrics { name: String::from("scope_seconds"), help: Some(String::from("Scope durations")) }
+            .write(&mut s, &series).unwrap();
+        assert_eq!(s, r#"# HELP scope_seconds Scope durations
+# TYPE scope_seconds histogram
+scope_seconds_bucket{key="re\"set",le="0.5"} 1
+scope_seconds_bucket{key="re\"set",le="1.0"} 3
+scope_seconds_bucket{key="re\"set",le="+Inf"} 3
+scope_seconds_sum{key="re\"set"} 2.2
+scope_seconds_count{key="re\"set"} 3
+scope_seconds_bucket{key="recurse",le="0.5"} 0
+scope_seconds_bucket{key="recurse",le="1.0"} 1
+scope_seconds_bucket{key="recurse",le="+Inf"} 1
+scope_seconds_sum{key="recurse"} 0.75
+scope_seconds_count{key="recurse"} 1
+# EOF
+"#);
+
+        let mut s = String::new();
+        OpenMetrics { name: String::from("interval"), help: None }
+            .write(&mut s, &[Series { labels: vec![], buckets: time_buckets(), sum: d("1") }]).unwrap();
+        assert!(s.contains("\ninterval_bucket{le=\"+Inf\"} 3\ninterval_sum 1\ninterval_count 3\n"));
+
+        assert!(OpenMetrics::valid_name("histo_log:duration_seconds"));
+        assert!(!OpenMetrics::valid_name("1st"));
+        assert!(!OpenMetrics::valid_name("a-b"));
+        assert!(!OpenMetrics::valid_name(""));
+
+        assert!(OpenMetrics::valid_label_name("key1"));
+        assert!(!OpenMetrics::valid_label_name("le"));
+        assert!(!OpenMetrics::valid_label_name("a:b"));
+        // a value on a bound counts towards that bound's bucket
+        let values = [d("0.25"), d("0.5"), d("1.0")];
+        let series = Series::new(vec![], time_buckets(), &values);
+        let counts: Vec<i64> = series.buckets.iter().map(|b| b.count).collect();
+        assert_eq!(counts, vec![2, 1]);
+        assert_eq!(series.sum, d("1.75"));
+
+        let reserved = Series { labels: vec![(String::from("le"), String::from("x"))], buckets: time_buckets(), sum: d("1") };
+        assert!(matches!(OpenMetrics { name: String::from("interval"), help: None }.write(&mut String::new(), &[reserved]),
+                         Err(Error::InvalidLabelName(_))));
+    }
+
+    #[test]
+    fn test_stats_table() {
+        let rows = vec![
+            (String::from("reset"), Stats::new(&[d("900.1583"), d("203.8183")]).unwrap()),
+            (String::from("a,b"), Stats::new(&[d("1")]).unwrap()),
+            (String::from("日本"), Stats::new(&[d("1")]).unwrap()),
+        ];
+        let table = StatsTable { label: String::from("key") };
+
+        let mut s = String::new();
+        table.write_text(&mut s, &rows).unwrap();
+        assert_eq!(s, "key    count       p50       p99
+reset      2  203.8183  900.1583
+a,b        1         1         1
+日本       1         1         1
+");
+
+        let mut s = String::new();
+        table.write_csv(&mut s, &rows).unwrap();
+        assert_eq!(s, "key,count,p50,p99
+reset,2,203.8183,900.1583
+\"a,b\",1,1,1
+日本,1,1,1
+");
+    }
+
+    #[test]
+    fn test_grouped() {
+        let groups = vec![
+            Group { key: vec![(String::from("scope"), String::from("reset"))], buckets: time_buckets() },
+            Group { key: vec![(String::from("scope"), String::from("a,b"))], buckets: vec![
+                Bucket::range(d("0"), d("0.5"), 0),
+                Bucket::range(d("0.5"), d("1.0"), 1),
+            ] },
+        ];
+
+        let mut s = String::new();
+        Grouped.write_csv(&mut s, &groups).unwrap();
+        assert_eq!(s, "scope,label,lower,upper,count,percent
+reset,0.5,0,0.5,1,33.3333
+reset,1.0,0.5,1.0,2,66.6667
+\"a,b\",0.5,0,0.5,0,0
+\"a,b\",1.0,0.5,1.0,1,100
+");
+
+        let mut s = String::new();
+        Grouped.write_tsv(&mut s, &groups[..1]).unwrap();
+        assert_eq!(s, "scope\tlabel\tlower\tupper\tcount\tpercent
+reset\t0.5\t0\t0.5\t1\t33.3333
+reset\t1.0\t0.5\t1.0\t2\t66.6667
+");
+
+        let mut s = String::new();
+        Grouped.write_json(&mut s, &groups).unwrap();
+        assert_eq!(s, r#"{"groups":[{"scope":"reset","total":3,"buckets":[{"label":"0.5","lower":0,"upper":0.5,"count":1,"percent":33.3333},{"label":"1.0","lower":0.5,"upper":1.0,"count":2,"percent":66.6667}]},{"scope":"a,b","total":1,"buckets":[{"label":"0.5","lower":0,"upper":0.5,"count":0,"percent":0},{"label":"1.0","lower":0.5,"upper":1.0,"count":1,"percent":100}]}]}
+"#);
+
+        let mut s = String::new();
+        Grouped.write_ndjson(&mut s, &groups[1..]).unwrap();
+        assert_eq!(s, r#"{"scope":"a,b","label":"0.5","lower":0,"upper":0.5,"count":0,"percent":0}
+{"scope":"a,b","label":"1.0","lower":0.5,"upper":1.0,"count":1,"percent":100}
+"#);
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(Json.render_to_string(&time_buckets()).unwrap(),
+                   r#"{"total":3,"buckets":[{"label":"0.5","lower":0,"upper":0.5,"count":1,"percent":33.3333},{"label":"1.0","lower":0.5,"upper":1.0,"count":2,"percent":66.6667}]}
+"#);
+        assert_eq!(Ndjson.render_to_string(&simple_buckets()).unwrap(),
+                   r#"{"label":"a,\"b\"","lower":null,"upper":null,"count":1,"percent":25}
+{"label":"c\td","lower":null,"upper":null,"count":3,"percent":75}
+"#);
+        let mut bytes: Vec<u8> = Vec::new();
+        Json.render_io(&time_buckets(), &mut bytes).unwrap();
+        assert_eq!(String::from_utf8(bytes).unwrap(), Json.render_to_string(&time_buckets()).unwrap());
+        assert_eq!(json_error(&super::super::Error::no_data()),
+                   r#"{"error":"No data found, check your inputs and selectors"}"#);
+    }
+}
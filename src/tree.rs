@@ -0,0 +1,311 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// (C) Copyright 2023-2024 Greg Whiteley
+
+// Call trees rebuilt from nested enter/exit log lines
+
+use std::fmt::Write;
+use regex::Regex;
+use rust_decimal::prelude::*;
+use unicode_width::UnicodeWidthStr;
+
+use super::graph::pad_left;
+use super::data::{time_from, LineVisitor, ScopeDiagnostics, ScopeEvent};
+use super::Result;
+
+/// Calls to one frame at one position in the call tree
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub name: String,
+    pub calls: usize,
+    /// Inclusive time - including the frames called from this one
+    pub total: Decimal,
+    /// Exclusive time - excluding the frames called from this one
+    pub self_time: Decimal,
+    pub children: Vec<Frame>,
+}
+
+impl Frame {
+    fn child(&mut self, name: &str) -> &mut Frame {
+        let ix = match self.children.iter().position(|c| c.name == name) {
+            Some(ix) => ix,
+            None => {
+                self.children.push(Frame { name: String::from(name), ..Default::default() });
+                self.children.len() - 1
+            }
+        };
+        &mut self.children[ix]
+    }
+
+    fn add(&mut self, path: &[String], total: Decimal, child_time: Decimal) {
+        let frame = path.iter().fold(self, |frame, name| frame.child(name));
+        frame.calls += 1;
+        frame.total += total;
+        frame.self_time += total - child_time;
+    }
+
+    // Most expensive children first, all the way down
+    fn sort(&mut self) {
+        self.children.sort_by(|a, b| b.total.cmp(&a.total));
+        for child in &mut self.children {
+            child.sort();
+        }
+    }
+
+    // Depth first, with each frame's path from the root
+    fn visit<'a, F>(&'a self, path: &mut Vec<&'a str>, f: &mut F) -> Result<()>
+    where
+        F: FnMut(&[&'a str], &'a Frame) -> Result<()>
+    {
+        for child in &self.children {
+            path.push(&child.name);
+            f(path, child)?;
+            child.visit(path, f)?;
+            path.pop();
+        }
+        Ok(())
+    }
+
+    /// Table of calls, total and self time, indenting called frames
+    /// beneath their callers
+    pub fn write_report(&self, w: &mut dyn Write) -> Result<()> {
+        let mut rows: Vec<[String; 4]> = vec![];
+        self.visit(&mut vec![], &mut |path, frame| {
+            rows.push([frame.calls.to_string(), frame.total.to_string(), frame.self_time.to_string(),
+                       format!("{}{}", "  ".repeat(path.len() - 1), frame.name)]);
+            Ok(())
+        })?;
+
+        let headings = ["calls", "total", "self", "frame"];
+        let mut widths = headings.map(str::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.width());
+            }
+        }
+        for row in std::iter::once(&headings.map(String::from)).chain(&rows) {
+            writeln!(w, "{}  {}  {}  {}", pad_left(&row[0], widths[0]), pad_left(&row[1], widths[1]),
+                     pad_left(&row[2], widths[2]), row[3])?;
+        }
+        Ok(())
+    }
+
+    /// One row per frame, identified by its ';' separated path
+    pub fn write_csv(&self, w: &mut dyn Write) -> Result<()> {
+        writeln!(w, "path,calls,total,self")?;
+        self.visit(&mut vec![], &mut |path, frame| {
+            let path = path.join(";");
+            if path.contains([',', '"', '\n', '\r']) {
+                write!(w, "\"{}\"", path.replace('"', "\"\""))?;
+            } else {
+                write!(w, "{}", path)?;
+            }
+            writeln!(w, ",{},{},{}", frame.calls, frame.total, frame.self_time)?;
+            Ok(())
+        })
+    }
+
+    /// Brendan Gregg's folded stack format, "a;b;c <self-time>", for
+    /// flamegraph tools.  Those expect integer sample counts, so self
+    /// times are written in units of the finest resolution in the input,
+    /// eg 1.25s is written as 125 if the input has times to 0.01s.
+    pub fn write_folded(&self, w: &mut dyn Write) -> Result<()> {
+        let mut scale = 0;
+        self.visit(&mut vec![], &mut |_, frame| {
+            scale = scale.max(frame.self_time.scale());
+            Ok(())
+        })?;
+        self.visit(&mut vec![], &mut |path, frame| {
+            if frame.self_time > Decimal::ZERO {
+                let mut samples = frame.self_time;
+                samples.rescale(scale);
+                writeln!(w, "{} {}", path.join(";"), samples.mantissa())?;
+            }
+            Ok(())
+        })
+    }
+}
+
+// A frame entered but not yet exited
+struct Open {
+    name: String,
+    line: usize,
+    start: Decimal,
+    child_time: Decimal,
+}
+
+// The "name" capture, else the first capture, else the whole match
+fn name_from(regex: &Regex, s: &str) -> Option<String> {
+    let captures = regex.captures(s)?;
+    captures.name("name")
+        .or_else(|| captures.get(1))
+        .or_else(|| captures.get(0))
+        .map(|m| String::from(m.as_str()))
+}
+
+// Close the innermost open frame at now, adding it to the tree and its
+// time to its caller's child time
+fn close(root: &mut Frame, stack: &mut Vec<Open>, now: Decimal) -> Option<Open> {
+    let path: Vec<String> = stack.iter().map(|open| open.name.clone()).collect();
+    let open = stack.pop()?;
+    let total = now - open.start;
+    root.add(&path, total, open.child_time);
+    if let Some(parent) = stack.last_mut() {
+        parent.child_time += total;
+    }
+    Some(open)
+}
+
+/// Rebuild the call tree from enter and exit lines, returning a root
+/// frame whose children are the outermost frames
+pub fn tree_load(inp: Vec<String>, time_select: &Regex, enter: &Regex, exit: &Regex) -> (Frame, ScopeDiagnostics) {
+    tree_parse(LineVisitor::new(inp), time_select, enter, exit)
+}
+
+fn tree_parse<I>(inp: I, time_select: &Regex, enter: &Regex, exit: &Regex) -> (Frame, ScopeDiagnostics)
+where
+    I: Iterator<Item = String>
+{
+    let mut root = Frame::default();
+    let mut diagnostics = ScopeDiagnostics::default();
+    let mut stack: Vec<Open> = vec![];
+    let mut last = None;
+
+    for (line, x) in (1..).zip(inp) {
+        if let Some(now) = time_from(x.as_str(), time_select) {
+            last = Some(now);
+            if let Some(name) = name_from(enter, &x) {
+                stack.push(Open { name, line, start: now, child_time: Decimal::ZERO });
+            } else if let Some(name) = name_from(exit, &x) {
+                let ix = match stack.iter().rposition(|open| open.name == name) {
+                    Some(ix) => ix,
+                    None => {
+                        diagnostics.unmatched_exits.push(ScopeEvent { line, key: vec![name] });
+                        continue;
+                    }
+                };
+                // frames left open inside the one exiting are closed with it
+                let mut abandoned = vec![];
+                while stack.len() > ix + 1 {
+                    let open = close(&mut root, &mut stack, now).expect("frames above");
+                    abandoned.push(ScopeEvent { line: open.line, key: vec![open.name] });
+                }
+                diagnostics.abandoned.extend(abandoned.into_iter().rev());
+                close(&mut root, &mut stack, now);
+            }
+        }
+    }
+
+    // frames never exited are closed at the last line, so their time
+    // still includes their children's
+    let mut open_at_eof = vec![];
+    if let Some(now) = last {
+        while let Some(open) = close(&mut root, &mut stack, now) {
+            open_at_eof.push(ScopeEvent { line: open.line, key: vec![open.name] });
+        }
+    }
+    diagnostics.open_at_eof.extend(open_at_eof.into_iter().rev());
+    root.sort();
+    (root, diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn r(s: &str) -> Regex {
+        Regex::new(s).unwrap_or_else(|_| panic!("regex failed to compile '{}'", s))
+    }
+
+    fn d(s: &str) -> Decimal {
+        Decimal::from_str_exact(s).unwrap()
+    }
+
+    fn parse(s: &str) -> (Frame, ScopeDiagnostics) {
+        tree_parse(s.lines().map(String::from), &r(r"^(\d+\.\d+)"),
+                   &r(r"->(?<name>\w+)"), &r(r"<-(?<name>\w+)"))
+    }
+
+    #[test]
+    fn test_tree_parse() {
+        let (tree, diagnostics) = parse("1.0 ->a\n2.0 ->b\n4.0 <-b\n5.0 ->c\n5.5 <-c\n7.0 <-a\n8.0 ->b\n9.0 <-b\n");
+        assert!(diagnostics.is_empty());
+        let names: Vec<&str> = tree.children.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+
+        let a = &tree.children[0];
+        assert_eq!((a.calls, a.total, a.self_time), (1, d("6.0"), d("3.5")));
+        let names: Vec<&str> = a.children.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["b", "c"]);
+        assert_eq!((a.children[0].calls, a.children[0].total), (1, d("2.0")));
+
+        // b at the top level is a different frame to b called from a
+        assert_eq!((tree.children[1].calls, tree.children[1].total), (1, d("1.0")));
+    }
+
+    #[test]
+    fn test_tree_unmatched() {
+        let (tree, diagnostics) = parse("1.0 <-x\n2.0 ->a\n3.0 ->b\n4.0 <-a\n5.0 ->c\n");
+        assert_eq!(diagnostics.unmatched_exits, vec![ScopeEvent { line: 1, key: vec![String::from("x")] }]);
+        assert_eq!(diagnostics.abandoned, vec![ScopeEvent { line: 3, key: vec![String::from("b")] }]);
+        assert_eq!(diagnostics.open_at_eof, vec![ScopeEvent { line: 5, key: vec![String::from("c")] }]);
+        // b is closed by the exit of a, so counts as a's child, and c is
+        // closed at the last line
+        let names: Vec<&str> = tree.children.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "c"]);
+        assert_eq!((tree.children[1].calls, tree.children[1].total), (1, d("0.0")));
+        let a = &tree.children[0];
+        assert_eq!((a.calls, a.total, a.self_time), (1, d("2.0"), d("1.0")));
+        assert_eq!((a.children[0].calls, a.children[0].total), (1, d("1.0")));
+    }
+
+    #[test]
+    fn test_tree_open_at_eof() {
+        let (tree, diagnostics) = parse("1.0 ->a\n2.0 ->b\n3.0 <-b\n10.0 ->a\n11.0 ->b\n19.0 <-b\n");
+        assert_eq!(diagnostics.open_at_eof.iter().map(|e| e.line).collect::<Vec<_>>(), vec![1, 4]);
+        let a = &tree.children[0];
+        assert_eq!((a.calls, a.total, a.self_time), (1, d("18.0"), d("8.0")));
+        // the inner a's subtree now sorts above the 1.0 b
+        let names: Vec<&str> = a.children.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+        assert_eq!((a.children[0].calls, a.children[0].total, a.children[0].self_time), (1, d("9.0"), d("1.0")));
+    }
+
+    #[test]
+    fn test_tree_report() {
+        let (tree, _) = tree_parse(include_str!("../tests/example_scoped.txt").lines().map(String::from),
+                                   &r(r"^(\d+\.\d+)"), &r(r"->(?<name>reset|waitReset)"), &r(r"<-(?<name>reset|waitReset)"));
+        let mut s = String::new();
+        tree.write_report(&mut s).unwrap();
+        // the second reset is never exited, so is closed at the last line
+        assert_eq!(s, "calls      total       self  frame
+    2  9571.1027  8677.1352  reset
+    2   690.1492   690.1492    waitReset
+    1   203.8183   203.8183    reset
+");
+
+        let (tree_wide, _) = parse("1.0 ->日本\n12.5 <-日本\n");
+        let mut s_wide = String::new();
+        tree_wide.write_report(&mut s_wide).unwrap();
+        assert_eq!(s_wide, "calls  total  self  frame\n    1   11.5  11.5  日本\n");
+
+        let mut s = String::new();
+        tree.write_csv(&mut s).unwrap();
+        assert_eq!(s, "path,calls,total,self
+reset,2,9571.1027,8677.1352
+reset;waitReset,2,690.1492,690.1492
+reset;reset,1,203.8183,203.8183
+");
+    }
+
+    #[test]
+    fn test_tree_folded() {
+        let (tree, _) = parse("1.0 ->a\n2.0 ->b\n4.25 <-b\n5.0 ->c\n5.5 <-c\n7.0 <-a\n7.0 ->b\n7.0 <-b\n");
+        let mut s = String::new();
+        tree.write_folded(&mut s).unwrap();
+        // zero self time frames are left out
+        assert_eq!(s, "a 325
+a;b 225
+a;c 50
+");
+    }
+}
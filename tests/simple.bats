@@ -112,6 +112,124 @@ setup() {
     6000: 1 #############################" ]
 }
 
+@test "scoped: --pairing fifo" {
+  run "$histo" scoped --pairing fifo --show-counts --time-delta=1000 --scope-in="->recurse" --scope-out="<-recurse" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "    1000: 1 #############################
+    2000: 0
+    3000: 1 #############################
+    4000: 2 ###########################################################" ]
+}
+
+@test "scoped: --scope-match toggles" {
+  run "$histo" scoped --show-counts --time-delta=100 --scope-match="(?:->|<-)(waitReset)" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "     300: 1 ###########################################################
+     400: 0
+     500: 1 ###########################################################" ]
+}
+
+@test "scoped: --group-by-capture" {
+  # the flag alone leaves the input file to the positional argument
+  run "$histo" scoped --unmatched ignore --show-counts --time-delta=2000 --scope-in="->(reset|recurse)" --scope-out="<-(reset|recurse)" --group-by-capture "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "key=recurse
+    2000: 2 ###########################################################
+    4000: 1 #############################
+    6000: 1 #############################
+
+key=reset
+    2000: 2 ###########################################################
+    4000: 0
+    6000: 0" ]
+
+  run "$histo" scoped --unmatched ignore --group-by-capture=table --scope-in="->(reset|recurse)" --scope-out="<-(reset|recurse)" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "key          count       p50        p99
+key=recurse      4   60.2755  5699.9640
+key=reset        2  203.8183   900.1583" ]
+}
+
+@test "scoped: --scope-timeout" {
+  run "$histo" scoped --scope-timeout 3000 --show-counts --time-delta=1000 --scope-in="->recurse" --scope-out="<-recurse" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "2 unmatched scope exit(s)
+  line 20
+  line 23
+2 scope(s) timed out
+  line 14
+  line 15
+    1000: 2 ###########################################################" ]
+
+  run "$histo" scoped --scope-timeout=-1 --scope-in="->recurse" --scope-out="<-recurse" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 2 ]
+  [ "${lines[0]}" = "error: Invalid value '-1' for '--scope-timeout <seconds>': Timeout -1 must not be negative" ]
+}
+
+@test "scoped: --concurrency" {
+  run "$histo" scoped --concurrency levels --show-counts --scope-in="->recurse" --scope-out="<-recurse" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "       0: 48659610 ####################################################
+       1: 24129468 ##########################
+       2: 32277417 ##################################
+       3:   602755 #
+
+time at each level in units of 0.0001" ]
+
+  run "$histo" scoped --concurrency levels --time-delta 1 --show-counts --scope-in="->recurse" --scope-out="<-recurse" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "       0: 4866 ########################################################
+       1: 2413 ############################
+       2: 3228 #####################################
+       3:   60 #
+
+time at each level in units of 1" ]
+
+  run "$histo" scoped --concurrency peak --time-delta 2000 --show-counts --scope-in="->recurse" --scope-out="<-recurse" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "    2000: 0
+    4000: 0
+    6000: 1 ###################
+    8000: 3 ###########################################################
+   10000: 3 ###########################################################
+   12000: 2 #######################################" ]
+}
+
+@test "scoped: --scopes" {
+  run "$histo" scoped --group-by-capture=table --scopes "$test_dir"/example_scopes.tsv "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "reset:
+1 scope(s) still open at end of input
+  line 7
+key            count       p50        p99
+scope=recurse      4   60.2755  5699.9640
+scope=reset        2  203.8183   900.1583" ]
+
+  run "$histo" scoped --unmatched error --scopes "$test_dir"/example_scopes.tsv "$test_dir"/example_scoped.txt
+  [ "$status" -eq 1 ]
+}
+
+@test "scoped: --scopes --output csv" {
+  run "$histo" --output csv scoped --unmatched ignore --time-delta=1000 --scopes "$test_dir"/example_scopes.tsv "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "${lines[0]}" = "scope,label,lower,upper,count,percent" ]
+  [ "${lines[1]}" = "recurse,1000,0,1000,2,50" ]
+  [ "${lines[7]}" = "reset,1000,0,1000,2,100" ]
+  [ "${#lines[@]}" -eq 13 ]
+}
+
+@test "scoped: named captures key by name" {
+  run "$histo" scoped --unmatched ignore --group-by-capture=table --scope-in="->(?<op>reset|recurse)" --scope-out="(<)-(?<op>reset|recurse)" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "key         count       p50        p99
+op=recurse      4   60.2755  5699.9640
+op=reset        2  203.8183   900.1583" ]
+
+  run "$histo" scoped --scope-in="->(?<op>reset|recurse)" --scope-out="<-(?<name>reset|recurse)" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 1 ]
+  echo "$output" | grep -q "Scoped regexes don't have matching captures"
+}
+
 @test "scoped: arg conflict" {
   run "$histo" scoped --scope-in="->recurse" --scope-match="<-recurse" "$test_dir"/example_scoped.txt
   [ "$status" -ne 0 ]
@@ -131,6 +249,22 @@ setup() {
   echo "$output" | grep -q "No data found"
 }
 
+@test "scoped: --unmatched" {
+  run "$histo" scoped --show-counts --time-delta=1000 --scope-in="->(reset)" --scope-out="<-(reset)" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "1 scope(s) still open at end of input
+  line 7 key=reset
+    1000: 2 ###########################################################" ]
+
+  run "$histo" scoped --unmatched ignore --show-counts --time-delta=1000 --scope-in="->reset" --scope-out="<-reset" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "    1000: 2 ###########################################################" ]
+
+  run "$histo" scoped --unmatched error --scope-in="->reset" --scope-out="<-reset" "$test_dir"/example_scoped.txt
+  [ "$status" -ne 0 ]
+  echo "$output" | grep -q "1 scope entries or exits could not be paired"
+}
+
 # TODO - add helpers for strace mode
 @test "scoped: strace" {
   run "$histo" scoped --show-counts --time-delta=0.0002 --time-select="\d+:\d+:(\d+\.\d+)" --scope-in="openat\(.*\) = (\d)" --scope-out="close\((\d)\)" "$test_dir"/strace.txt
@@ -140,3 +274,201 @@ setup() {
   0.0006: 3 ###########################################################
   0.0008: 1 ###################" ]
 }
+
+@test "time-diff --style unicode" {
+  run "$histo" --style unicode time-diff --time-delta=200 "$test_dir"/example.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "     200 █████████████████████████████████████████████████▌
+     400 ██████████████████████████████████████████████████████████████
+     600 ██████████████████▌
+     800 ████████████████████████▊
+    1000 ██████▏
+    1200 ██████▏
+    1400 ██████▏
+    1600
+    1800
+    2000 ██████▏
+    2200
+    2400
+    2600
+    2800
+    3000 ██████▏" ]
+}
+
+@test "--color always with thresholds" {
+  run "$histo" --color always --warn-above 3000 --crit-above 5000 scoped --show-counts --time-delta=1000 --scope-in="->recurse" --scope-out="<-recurse" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "$(printf '    1000: 2 \033[32m###########################################################\033[0m
+\033[2m    2000: 0\033[0m
+\033[2m    3000: 0\033[0m
+    4000: 1 \033[33m#############################\033[0m
+\033[2m    5000: 0\033[0m
+    6000: 1 \033[31m#############################\033[0m')" ]
+}
+
+@test "--color auto honours NO_COLOR" {
+  NO_COLOR=1 run "$histo" --color auto simple "$test_dir"/seq.txt
+  [ "$status" -eq 0 ]
+  ! echo "$output" | grep -q $'\033'
+}
+
+@test "scoped: --format columns" {
+  run "$histo" --format columns --height 4 --show-counts scoped --time-delta=1000 --scope-in="->recurse" --scope-out="<-recurse" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "####
+####
+####           ####      ####
+####           ####      ####
+-----------------------------
+1000 2000 3000 4000 5000 6000
+2    0    0    1    0    1" ]
+}
+
+@test "scoped: --show-percent --show-cumulative" {
+  run "$histo" --show-percent --show-cumulative scoped --time-delta=1000 --scope-in="->recurse" --scope-out="<-recurse" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "    1000: 50.0%  50.0% ################################################
+    2000:  0.0%  50.0%
+    3000:  0.0%  50.0%
+    4000: 25.0%  75.0% ########################
+    5000:  0.0%  75.0%
+    6000: 25.0% 100.0% ########################" ]
+}
+
+@test "scoped: --bar-scale log" {
+  run "$histo" --bar-scale log scoped --time-delta=1000 --scope-in="->recurse" --scope-out="<-recurse" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "    1000 ##############################################################
+    2000
+    3000
+    4000 #######################################
+    5000
+    6000 #######################################
+         (log scale)" ]
+}
+
+@test "scoped: --title --legend" {
+  run "$histo" --title "recurse" --legend --show-counts scoped --time-delta=1000 --scope-in="->recurse" --scope-out="<-recurse" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "recurse
+duration
+    1000: 2 ###########################################################
+    2000: 0
+    3000: 0
+    4000: 1 #############################
+    5000: 0
+    6000: 1 #############################
+            # ≈ 0.03 events, 4 samples, bucket width 1000" ]
+}
+
+@test "time-diff --format spark" {
+  run "$histo" --format spark --spark-labels time-diff --time-delta=200 "$test_dir"/example.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "200 ▆█▂▃▁▁▁  ▁    ▁ 3000" ]
+}
+
+@test "scoped: --output csv" {
+  run "$histo" --output csv scoped --time-delta=1000 --scope-in="->recurse" --scope-out="<-recurse" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "label,lower,upper,count,percent
+1000,0,1000,2,50
+2000,1000,2000,0,0
+3000,2000,3000,0,0
+4000,3000,4000,1,25
+5000,4000,5000,0,0
+6000,5000,6000,1,25" ]
+}
+
+@test "--output json reports errors as json" {
+  run "$histo" --output json scoped --scope-in="->output" --scope-out="<-input" "$test_dir"/example_scoped.txt
+  [ "$status" -ne 0 ]
+  echo "$output" | grep -q '^{"error":"No data found'
+}
+
+@test "scoped: --output svg" {
+  run "$histo" --output svg --title "recurse" scoped --time-delta=1000 --scope-in="->recurse" --scope-out="<-recurse" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "${lines[0]:0:5}" = "<svg " ]
+  echo "$output" | grep -q "<title>3000 - 4000: 1 (25%)</title>"
+  echo "$output" | grep -q ">4 samples, 6 buckets, bucket width 1000, peak 2 at 1000<"
+}
+
+@test "scoped: --output gnuplot" {
+  run "$histo" --output gnuplot scoped --time-delta=1000 --scope-in="->recurse" --scope-out="<-recurse" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  echo "$output" | grep -q '^"4000" 3000 4000 1$'
+  echo "$output" | grep -q '^plot \$data'
+}
+
+@test "simple: --output vega-lite" {
+  run "$histo" --output vega-lite simple --match 2 "$test_dir"/seq.txt
+  [ "$status" -eq 0 ]
+  echo "$output" | grep -q '"values":\[{"label":"12","lower":null,"upper":null,"count":1,"percent":33.3333},'
+}
+
+@test "scoped: --output markdown" {
+  run "$histo" --output markdown scoped --time-delta=1000 --scope-in="->recurse" --scope-out="<-recurse" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "| duration | count | percent | distribution |
+|---:|---:|---:|:---|
+| 1000 | 2 | 50.0% | ############################## |
+| 2000 | 0 | 0.0% |  |
+| 3000 | 0 | 0.0% |  |
+| 4000 | 1 | 25.0% | ############### |
+| 5000 | 0 | 0.0% |  |
+| 6000 | 1 | 25.0% | ############### |" ]
+}
+
+@test "scoped: --output openmetrics" {
+  run "$histo" --output openmetrics --metric-name scope_duration scoped --time-delta=2000 --scope-in="->(reset|recurse)" --scope-out="<-(reset|recurse)" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  echo "$output" | grep -q '^# TYPE scope_duration histogram$'
+  echo "$output" | grep -q '^scope_duration_bucket{key="recurse",le="4000"} 3$'
+  echo "$output" | grep -q '^scope_duration_bucket{key="reset",le="+Inf"} 2$'
+  echo "$output" | grep -q '^scope_duration_sum{key="reset"} 1103.9766$'
+  echo "$output" | grep -q '^scope_duration_count{key="recurse"} 4$'
+  [ "${lines[-1]}" = "# EOF" ]
+
+  run "$histo" --output openmetrics scoped --unmatched ignore --scope-in="->(?<le>reset)" --scope-out="<-(?<le>reset)" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 1 ]
+  echo "$output" | grep -q "Invalid OpenMetrics label name le"
+}
+
+@test "simple: --output openmetrics is not supported" {
+  run "$histo" --output openmetrics simple "$test_dir"/seq.txt
+  [ "$status" -ne 0 ]
+  echo "$output" | grep -q "not supported"
+}
+
+@test "tree" {
+  run "$histo" tree --enter="->(?<name>\w+)" --exit="<-(?<name>\w+)" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "1 scope(s) abandoned by an enclosing exit
+  line 7 name=reset
+calls      total       self  frame
+    2  5700.9640  1867.1709  recurse
+    1  3288.0172  2274.1613    recurse
+    1  1013.8559   953.5804      other
+    1    60.2755    60.2755        recurse
+    1   545.7759   545.7759    other
+    1  2520.3173    42.0574  other
+    1  2478.2599  1866.2042    reset
+    1   408.2374   408.2374      waitReset
+    1   203.8183   203.8183      reset
+    1   900.1583   618.2465  reset
+    1   281.9118   281.9118    waitReset" ]
+}
+
+@test "tree: needs a name capture" {
+  run "$histo" tree --enter="->(\w+)" --exit="<-(?<name>\w+)" "$test_dir"/example_scoped.txt
+  [ "$status" -ne 0 ]
+  echo "$output" | grep -q 'Need a capture named "name"'
+}
+
+@test "tree: --output folded" {
+  run "$histo" --output folded tree --unmatched ignore --enter="->(?<name>reset|waitReset)" --exit="<-(?<name>reset|waitReset)" "$test_dir"/example_scoped.txt
+  [ "$status" -eq 0 ]
+  [ "$output" = "reset 86771352
+reset;waitReset 6901492
+reset;reset 2038183" ]
+}